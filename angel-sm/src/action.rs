@@ -1,17 +1,24 @@
 use crate::AngelJob;
 use crate::pfunc::ProcessFunction;
-use crate::util::{vec_or_single, deser_duration};
-use cthulhu_common::devinfo::DeviceInformation;
-use serde::Deserialize;
-use std::time::Duration;
+use crate::util::{vec_or_single, deser_duration, ser_duration};
+use cthulhu_common::devinfo::{DeviceInformation, DeviceInformationType};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
 use swexpect::SwitchExpect;
 use tracing::warn;
 
-#[derive(Deserialize, Clone, Debug, PartialOrd, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialOrd, PartialEq)]
 #[serde(untagged)]
 pub enum DeviceInfoArg {
     WithArgument(DeviceInformation),
     WithoutArgument { flag: DeviceInformation },
+    /// `key = "PoEFirmwareMismatch"`, with an optional `value` and `level` (defaults to `Info`).
+    Custom {
+        key: String,
+        value: Option<String>,
+        #[serde(default)]
+        level: DeviceInformationType,
+    },
 }
 
 impl From<DeviceInfoArg> for DeviceInformation {
@@ -19,11 +26,12 @@ impl From<DeviceInfoArg> for DeviceInformation {
         match value {
             DeviceInfoArg::WithArgument(e) => e,
             DeviceInfoArg::WithoutArgument { flag: e } => e,
+            DeviceInfoArg::Custom { key, value, level } => DeviceInformation::Custom { key, value, level },
         }
     }
 }
 
-#[derive(Deserialize, Clone, Debug, PartialOrd, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialOrd, PartialEq)]
 #[serde(tag = "type")]
 pub enum Action {
     Send {
@@ -36,6 +44,11 @@ pub enum Action {
     SendControl {
         char: char,
     },
+    /// A serial BREAK, e.g. to get a console into its ROM monitor. Only some ports can send one.
+    SendBreak {
+        #[serde(deserialize_with = "deser_duration", serialize_with = "ser_duration")]
+        duration: Duration,
+    },
     Function {
         func: ProcessFunction,
     },
@@ -45,7 +58,7 @@ pub enum Action {
         times: usize,
     },
     Delay {
-        #[serde(deserialize_with = "deser_duration")]
+        #[serde(deserialize_with = "deser_duration", serialize_with = "ser_duration")]
         duration: Duration,
     },
     AddDeviceInfo(DeviceInfoArg),
@@ -63,6 +76,20 @@ impl Action {
         p: &mut SwitchExpect,
         data: &str,
         mat: &str,
+    ) -> color_eyre::Result<()> {
+        let started = Instant::now();
+        let r = self.run(job, p, data, mat).await;
+        job.action_performed(self, started.elapsed(), r.as_ref().err())
+            .await;
+        r
+    }
+
+    async fn run<T: AngelJob>(
+        &self,
+        job: &mut T,
+        p: &mut SwitchExpect,
+        data: &str,
+        mat: &str,
     ) -> color_eyre::Result<()> {
         match self {
             Action::Send { text: s } => {
@@ -81,6 +108,7 @@ impl Action {
                 p.send_control(*c).await?;
                 Ok(())
             }
+            Action::SendBreak { duration } => job.send_break(*duration).await,
             Action::Function { func: pf } => pf.execute(job, p, data, mat).await,
             Action::FinishJob => {
                 job.finish_job().await?;
@@ -1,6 +1,6 @@
 use crate::action::Action;
 use crate::util::vec_or_single;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
 pub type State = String;
@@ -40,7 +40,7 @@ pub struct StateMachineTransition {
     pub actions: Vec<Action>,
 }
 
-#[derive(Deserialize, Clone, Debug, Eq, PartialOrd, PartialEq, Ord, Hash)]
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialOrd, PartialEq, Ord, Hash)]
 #[serde(tag = "type")]
 pub enum StateMachineTrigger {
     #[serde(rename = "string")]
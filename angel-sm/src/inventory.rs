@@ -0,0 +1,222 @@
+//! Parsers for the hardware inventory tables printed by the various CLIs.
+use cthulhu_common::devinfo::{ComponentStatus, Transceiver};
+use regex::Regex;
+
+fn lines(data: &str) -> impl Iterator<Item = &str> {
+    data.lines().map(|l| l.trim_end_matches('\r'))
+}
+
+/// Split a table row on runs of two or more spaces.
+fn columns(line: &str) -> Vec<&str> {
+    line.trim()
+        .split("  ")
+        .map(|c| c.trim())
+        .filter(|c| !c.is_empty())
+        .collect()
+}
+
+/// Status columns are words, measurements always contain a digit.
+fn status_words<'a>(words: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    words.take_while(|w| !w.chars().any(|c| c.is_ascii_digit())).collect()
+}
+
+/// `show chassis hardware`: Collect all the optics, named FPC/PIC/port.
+pub fn parse_junos_hardware(data: &str) -> color_eyre::Result<Vec<Transceiver>> {
+    let fpc_re = Regex::new(r"^FPC (?<fpc>\d+)")?;
+    let pic_re = Regex::new(r"^\s*PIC (?<pic>\d+)")?;
+    let xcvr_re = Regex::new(
+        r"^\s*Xcvr (?<port>\d+)\s+(?:REV \S+\s+)?(?<part>\S+)\s+(?<serial>\S+)\s*(?<desc>.*?)\s*$",
+    )?;
+
+    let mut fpc = "0".to_string();
+    let mut pic = "0".to_string();
+    let mut transceivers = Vec::new();
+    for line in lines(data) {
+        if let Some(c) = fpc_re.captures(line) {
+            fpc = c["fpc"].to_string();
+        } else if let Some(c) = pic_re.captures(line) {
+            pic = c["pic"].to_string();
+        } else if let Some(c) = xcvr_re.captures(line) {
+            let model = if c["desc"].is_empty() {
+                c["part"].to_string()
+            } else {
+                c["desc"].to_string()
+            };
+            transceivers.push(Transceiver {
+                port: format!("{fpc}/{pic}/{}", &c["port"]),
+                model,
+                serial: c["serial"].to_string(),
+            });
+        }
+    }
+    Ok(transceivers)
+}
+
+/// `show chassis environment`: Returns the power supplies and the fans.
+///
+/// Only the first row of every class carries the class name, so we track it across rows.
+pub fn parse_junos_environment(data: &str) -> (Vec<ComponentStatus>, Vec<ComponentStatus>) {
+    let mut psus = Vec::new();
+    let mut fans = Vec::new();
+    let mut class = String::new();
+    for line in lines(data) {
+        let rest = if line.starts_with(' ') {
+            line
+        } else {
+            let (c, rest) = line.split_once(' ').unwrap_or((line, ""));
+            class = c.to_string();
+            rest
+        };
+        let cols = columns(rest);
+        if cols.len() < 2 {
+            continue;
+        }
+        let status = ComponentStatus {
+            name: cols[0].to_string(),
+            status: cols[1].to_string(),
+        };
+        match class.as_str() {
+            "Power" => psus.push(status),
+            "Fans" => fans.push(status),
+            _ => {}
+        }
+    }
+    (psus, fans)
+}
+
+/// `show system license`: Returns the installed license features.
+pub fn parse_junos_licenses(data: &str) -> color_eyre::Result<Vec<String>> {
+    let r = Regex::new(r"^\s+(?<feature>[A-Za-z0-9\-_]+)\s+-\s+(?<desc>.+?)\s*$")?;
+    Ok(lines(data)
+        .skip_while(|l| !l.starts_with("Licenses installed"))
+        .filter_map(|l| r.captures(l))
+        .map(|c| format!("{} ({})", &c["feature"], &c["desc"]))
+        .collect())
+}
+
+/// `show interfaces terse`: Counts the physical network ports, logical units have a dot.
+pub fn parse_junos_port_count(data: &str) -> color_eyre::Result<u32> {
+    let r = Regex::new(r"^(?:fe|ge|xe|mge|et)-\d+/\d+/\d+\s")?;
+    Ok(lines(data).filter(|l| r.is_match(l)).count() as u32)
+}
+
+/// `show inventory`: Returns the total port count and the installed optics.
+pub fn parse_arista_inventory(data: &str) -> color_eyre::Result<(Option<u32>, Vec<Transceiver>)> {
+    let section_re = Regex::new(r"^System has (?<count>\d+) (?<section>[a-z ]+?)\s*$")?;
+
+    let mut section = String::new();
+    let mut port_count = None;
+    let mut transceivers = Vec::new();
+    for line in lines(data) {
+        if let Some(c) = section_re.captures(line) {
+            section = c["section"].to_string();
+            if section == "ports" {
+                port_count = Some(c["count"].parse()?);
+            }
+            continue;
+        }
+        if section != "switched transceiver slots" {
+            continue;
+        }
+        let cols = columns(line);
+        // Port, manufacturer, model, serial and revision; empty slots only say "Not Present".
+        if cols.len() >= 4 && cols[0].chars().all(|c| c.is_ascii_digit() || c == '/') {
+            transceivers.push(Transceiver {
+                port: cols[0].to_string(),
+                model: cols[2].to_string(),
+                serial: cols[3].to_string(),
+            });
+        }
+    }
+    Ok((port_count, transceivers))
+}
+
+/// `show environment power`: The status is the trailing text after the measurements.
+pub fn parse_arista_power(data: &str) -> Vec<ComponentStatus> {
+    let mut psus = Vec::new();
+    for line in lines(data) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() < 2 || !words[0].chars().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+        let mut status = status_words(words.iter().rev().copied());
+        status.reverse();
+        if !status.is_empty() {
+            psus.push(ComponentStatus {
+                name: format!("PowerSupply{}", words[0]),
+                status: status.join(" "),
+            });
+        }
+    }
+    psus
+}
+
+/// `show environment cooling`: The status directly follows the fan name.
+pub fn parse_arista_cooling(data: &str) -> color_eyre::Result<Vec<ComponentStatus>> {
+    let fan_re = Regex::new(r"^(?:PowerSupply)?\d+/\d+$")?;
+    let mut fans = Vec::new();
+    for line in lines(data) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() < 2 || !fan_re.is_match(words[0]) {
+            continue;
+        }
+        let status = status_words(words.iter().skip(1).copied());
+        if !status.is_empty() {
+            fans.push(ComponentStatus {
+                name: words[0].to_string(),
+                status: status.join(" "),
+            });
+        }
+    }
+    Ok(fans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn junos_environment() {
+        let data = "Class Item                           Status     Measurement\r\n\
+                    Power FPC 0 Power Supply 0           OK\r\n      \
+                    FPC 0 Power Supply 1           Absent\r\n\
+                    Temp  FPC 0 CPU                      OK         40 degrees C / 104 degrees F\r\n\
+                    Fans  FPC 0 Fan Tray 0 Fan 1         OK         Spinning at normal speed\r\n      \
+                    FPC 0 Fan Tray 0 Fan 2         Failed\r\n";
+        let (psus, fans) = parse_junos_environment(data);
+        assert_eq!(psus.len(), 2);
+        assert_eq!(psus[1].name, "FPC 0 Power Supply 1");
+        assert!(!psus[1].is_faulty());
+        assert_eq!(fans.len(), 2);
+        assert!(fans[1].is_faulty());
+    }
+
+    #[test]
+    fn junos_port_count() -> color_eyre::Result<()> {
+        let data = "Interface               Admin Link Proto    Local                 Remote\r\n\
+                    ge-0/0/0                up    down\r\n\
+                    ge-0/0/0.0              up    down eth-switch\r\n\
+                    xe-0/1/0                up    down\r\n\
+                    me0                     up    down\r\n";
+        assert_eq!(parse_junos_port_count(data)?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn arista_inventory() -> color_eyre::Result<()> {
+        let data = "System has 53 ports\r\n  \
+                    Type             Count\r\n  \
+                    ---------------- ----\r\n  \
+                    Switched         52\r\n\r\n\
+                    System has 52 switched transceiver slots\r\n  \
+                    Port Manufacturer     Model            Serial Number    Rev\r\n  \
+                    ---- ---------------- ---------------- ---------------- ----\r\n  \
+                    49   Arista Networks  QSFP-40G-SR4     XTY00000000      0002\r\n  \
+                    50   Not Present\r\n";
+        let (ports, transceivers) = parse_arista_inventory(data)?;
+        assert_eq!(ports, Some(53));
+        assert_eq!(transceivers.len(), 1);
+        assert_eq!(transceivers[0].serial, "XTY00000000");
+        Ok(())
+    }
+}
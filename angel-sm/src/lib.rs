@@ -1,4 +1,6 @@
+use crate::action::Action;
 use cthulhu_common::devinfo::DeviceInformation;
+use std::time::Duration;
 
 pub mod action;
 pub mod builder;
@@ -7,6 +9,7 @@ pub mod pfunc;
 pub mod state;
 pub mod trigger;
 
+mod inventory;
 mod util;
 
 //TODO: Figure out how to properly fix the warning.
@@ -17,4 +20,13 @@ pub trait AngelJob {
     async fn reset(&mut self) -> color_eyre::Result<()>;
     async fn add_information(&mut self, information: DeviceInformation) -> color_eyre::Result<()>;
     async fn get_job_config_key(&self, key: &str) -> Option<String>;
+    /// Hold the serial line in the BREAK condition for `duration`.
+    async fn send_break(&mut self, duration: Duration) -> color_eyre::Result<()>;
+    /// Called after every action, nested ones included, with how long it took and how it went.
+    async fn action_performed(
+        &mut self,
+        action: &Action,
+        elapsed: Duration,
+        error: Option<&color_eyre::Report>,
+    );
 }
@@ -1,11 +1,13 @@
 use crate::AngelJob;
+use crate::inventory;
 use color_eyre::eyre::Context;
-use cthulhu_common::devinfo::DeviceInformation;
-use regex::RegexBuilder;
-use serde::Deserialize;
+use cthulhu_common::devinfo::{ComponentStatus, DeviceInformation};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
 use swexpect::SwitchExpect;
+use swexpect::hay::ReadUntil;
 
-#[derive(Deserialize, Clone, Debug, PartialOrd, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialOrd, PartialEq)]
 pub enum ProcessFunction {
     FixFS,
     CaptureJunosVersion,
@@ -18,6 +20,17 @@ pub enum ProcessFunction {
     CaptureHPSwitchModel,
     CaptureHPSwitchSerial,
     CaptureHPOSVersionBanner,
+    CaptureIcxBootCodeVersion,
+    CaptureIcxVersion,
+    CaptureMikrotikRouterBoot,
+    CaptureMikrotikBoardInfo,
+    CaptureMikrotikSystemInfo,
+    CaptureJunosInventory,
+    CaptureAristaInventory,
+    CaptureAristaEnvironmentPower,
+    CaptureAristaEnvironmentCooling,
+    CheckJunosResidualConfig,
+    CheckResidualConfig,
 }
 
 impl ProcessFunction {
@@ -241,6 +254,238 @@ impl ProcessFunction {
                 }
                 Ok(())
             }
+            ProcessFunction::CaptureIcxBootCodeVersion => {
+                // The data directly follows the "Boot Code Version" banner.
+                let r = RegexBuilder::new(r"^\s*(?<version>[0-9]+\.[0-9A-Za-z\.]+)")
+                    .crlf(true)
+                    .build()?;
+                if let Some(cap) = r.captures(data)
+                    && let Some(version) = cap.name("version")
+                {
+                    job.add_information(DeviceInformation::BootloaderVersion(
+                        version.as_str().to_string(),
+                    ))
+                        .await?;
+                }
+                Ok(())
+            }
+            ProcessFunction::CaptureIcxVersion => {
+                let r = RegexBuilder::new(r"(?:HW: Stackable (?<model>[A-Za-z0-9\-]+))|(?:Serial\s+#:\s*(?<serial>[A-Za-z0-9]+))|(?:SW: Version (?<version>[0-9A-Za-z\.]+))")
+                    .multi_line(true)
+                    .crlf(true)
+                    .build()?;
+                for cap in r.captures_iter(data) {
+                    if let Some(model) = cap.name("model") {
+                        job.add_information(DeviceInformation::Model(model.as_str().to_string()))
+                            .await?;
+                    }
+                    if let Some(serial) = cap.name("serial") {
+                        job.add_information(DeviceInformation::SerialNumber(
+                            serial.as_str().to_string(),
+                        ))
+                            .await?;
+                    }
+                    if let Some(version) = cap.name("version") {
+                        job.add_information(DeviceInformation::SoftwareVersion(
+                            version.as_str().to_string(),
+                        ))
+                            .await?;
+                    }
+                }
+                Ok(())
+            }
+            ProcessFunction::CaptureMikrotikRouterBoot => {
+                // The data directly follows the "RouterBOOT booter" banner and contains the booter version and board name.
+                let r = RegexBuilder::new(r"^\s*(?<version>[0-9]+\.[0-9A-Za-z\.]+)\s+(?<board>[A-Za-z0-9\-\+]+)")
+                    .crlf(true)
+                    .build()?;
+                if let Some(cap) = r.captures(data) {
+                    if let Some(version) = cap.name("version") {
+                        job.add_information(DeviceInformation::BootloaderVersion(
+                            version.as_str().to_string(),
+                        ))
+                            .await?;
+                    }
+                    if let Some(board) = cap.name("board") {
+                        job.add_information(DeviceInformation::Model(board.as_str().to_string()))
+                            .await?;
+                    }
+                }
+                Ok(())
+            }
+            ProcessFunction::CaptureMikrotikBoardInfo => {
+                // "i - board info" in the RouterBOOT setup menu.
+                let r = RegexBuilder::new(r"(?:^\s*board type: (?<board>\S[^\r\n]*?)\s*$)|(?:^\s*serial number: (?<serial>[A-Za-z0-9]+)\s*$)|(?:^\s*firmware version: (?<firmware>[0-9A-Za-z\.]+)\s*$)")
+                    .case_insensitive(true)
+                    .multi_line(true)
+                    .crlf(true)
+                    .build()?;
+                for cap in r.captures_iter(data) {
+                    if let Some(board) = cap.name("board") {
+                        job.add_information(DeviceInformation::Model(board.as_str().to_string()))
+                            .await?;
+                    }
+                    if let Some(serial) = cap.name("serial") {
+                        job.add_information(DeviceInformation::SerialNumber(
+                            serial.as_str().to_string(),
+                        ))
+                            .await?;
+                    }
+                    if let Some(version) = cap.name("firmware") {
+                        job.add_information(DeviceInformation::BootloaderVersion(
+                            version.as_str().to_string(),
+                        ))
+                            .await?;
+                    }
+                }
+                Ok(())
+            }
+            ProcessFunction::CaptureMikrotikSystemInfo => {
+                let r = RegexBuilder::new(r"(?:^\s*board-name: (?<board>\S[^\r\n]*?)\s*$)|(?:^\s*serial-number: (?<serial>[A-Za-z0-9]+)\s*$)|(?:^\s*current-firmware: (?<firmware>[0-9A-Za-z\.]+)\s*$)|(?:^\s*version: (?<version>[0-9A-Za-z\.]+)(?: \(.*\))?\s*$)")
+                    .multi_line(true)
+                    .crlf(true)
+                    .build()?;
+                for cap in r.captures_iter(data) {
+                    if let Some(board) = cap.name("board") {
+                        job.add_information(DeviceInformation::Model(board.as_str().to_string()))
+                            .await?;
+                    }
+                    if let Some(serial) = cap.name("serial") {
+                        job.add_information(DeviceInformation::SerialNumber(
+                            serial.as_str().to_string(),
+                        ))
+                            .await?;
+                    }
+                    if let Some(version) = cap.name("firmware") {
+                        job.add_information(DeviceInformation::BootloaderVersion(
+                            version.as_str().to_string(),
+                        ))
+                            .await?;
+                    }
+                    if let Some(version) = cap.name("version") {
+                        job.add_information(DeviceInformation::SoftwareVersion(
+                            version.as_str().to_string(),
+                        ))
+                            .await?;
+                    }
+                }
+                Ok(())
+            }
+            ProcessFunction::CaptureJunosInventory => {
+                // The zeroize that follows also removes the licenses, so this runs its own
+                // commands at the prompt the wipe is started from.
+                let hardware = junos_cli(p, "show chassis hardware | no-more").await?;
+                let transceivers = inventory::parse_junos_hardware(&hardware)?;
+                if !transceivers.is_empty() {
+                    job.add_information(DeviceInformation::Transceivers(transceivers))
+                        .await?;
+                }
+
+                let environment = junos_cli(p, "show chassis environment | no-more").await?;
+                let (psus, fans) = inventory::parse_junos_environment(&environment);
+                add_power_supplies(job, psus).await?;
+                add_fans(job, fans).await?;
+
+                let interfaces = junos_cli(p, "show interfaces terse | no-more").await?;
+                let port_count = inventory::parse_junos_port_count(&interfaces)?;
+                if port_count > 0 {
+                    job.add_information(DeviceInformation::PortCount(port_count))
+                        .await?;
+                }
+
+                let licenses = junos_cli(p, "show system license | no-more").await?;
+                let licenses = inventory::parse_junos_licenses(&licenses)?;
+                if !licenses.is_empty() {
+                    job.add_information(DeviceInformation::Licenses(licenses))
+                        .await?;
+                }
+                Ok(())
+            }
+            ProcessFunction::CaptureAristaInventory => {
+                let (port_count, transceivers) = inventory::parse_arista_inventory(data)?;
+                if let Some(port_count) = port_count {
+                    job.add_information(DeviceInformation::PortCount(port_count))
+                        .await?;
+                }
+                if !transceivers.is_empty() {
+                    job.add_information(DeviceInformation::Transceivers(transceivers))
+                        .await?;
+                }
+                Ok(())
+            }
+            ProcessFunction::CaptureAristaEnvironmentPower => {
+                add_power_supplies(job, inventory::parse_arista_power(data)).await
+            }
+            ProcessFunction::CaptureAristaEnvironmentCooling => {
+                add_fans(job, inventory::parse_arista_cooling(data)?).await
+            }
+            ProcessFunction::CheckJunosResidualConfig => {
+                let r = RegexBuilder::new(r"^Count: (?<count>[0-9]+) lines")
+                    .multi_line(true)
+                    .crlf(true)
+                    .build()?;
+                let clean = match r.captures(data) {
+                    Some(caps) => caps["count"].parse::<usize>()? == 0,
+                    // Without a count we cannot tell, which is no better than residual config.
+                    None => false,
+                };
+                add_wipe_result(job, clean).await
+            }
+            ProcessFunction::CheckResidualConfig => {
+                // The state before this one consumes the echo of the command, comments are fine.
+                let residual = data
+                    .lines()
+                    .map(|l| l.trim())
+                    .filter(|l| !l.is_empty() && !l.starts_with('#') && !l.starts_with('!'))
+                    .count();
+                add_wipe_result(job, residual == 0).await
+            }
         }
     }
 }
+
+/// Run a command at a Junos CLI prompt and return what it printed in between.
+async fn junos_cli(p: &mut SwitchExpect, command: &str) -> color_eyre::Result<String> {
+    let prompt = ReadUntil::Regex(Regex::new(r"root(@[A-Za-z0-9\-]+)?>")?);
+    p.send_line(command).await?;
+    // Waiting for the echo first keeps a prompt that is still buffered from ending the output early.
+    p.exp_string(command)
+        .await
+        .with_context(|| format!("no echo of {command}"))?;
+    let (output, _) = p
+        .expect(&prompt)
+        .await
+        .with_context(|| format!("no prompt after {command}"))?;
+    Ok(output)
+}
+
+async fn add_power_supplies<T: AngelJob>(
+    job: &mut T,
+    psus: Vec<ComponentStatus>,
+) -> color_eyre::Result<()> {
+    if psus.is_empty() {
+        return Ok(());
+    }
+    if psus.iter().any(|p| p.is_faulty()) {
+        job.add_information(DeviceInformation::FaultyPowerSupply).await?;
+    }
+    job.add_information(DeviceInformation::PowerSupplies(psus)).await
+}
+
+async fn add_fans<T: AngelJob>(job: &mut T, fans: Vec<ComponentStatus>) -> color_eyre::Result<()> {
+    if fans.is_empty() {
+        return Ok(());
+    }
+    if fans.iter().any(|f| f.is_faulty()) {
+        job.add_information(DeviceInformation::FaultyFan).await?;
+    }
+    job.add_information(DeviceInformation::Fans(fans)).await
+}
+
+async fn add_wipe_result<T: AngelJob>(job: &mut T, clean: bool) -> color_eyre::Result<()> {
+    if clean {
+        job.add_information(DeviceInformation::WipeVerified).await
+    } else {
+        job.add_information(DeviceInformation::DidNotWipe).await
+    }
+}
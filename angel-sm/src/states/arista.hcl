@@ -182,13 +182,78 @@ state "AristaEnable" {
   }
 }
 
+# EOS only comes up after the startup-config is gone, with the old one we could not log in.
+# The inventory is all hardware, so it reads the same after the erase.
 state "AristaEraseCores" {
   transition {
-    target = "HookAristaCLI"
+    target = "AristaInventoryOutput"
+    trigger {
+      type   = "string"
+      string = "localhost#"
+    }
+    action {
+      type = "SendLine"
+      line = "show inventory | no-more"
+    }
+  }
+}
+
+state "AristaInventoryOutput" {
+  transition {
+    target = "AristaEnvironmentPowerOutput"
+    trigger {
+      type   = "string"
+      string = "localhost#"
+    }
+    action {
+      type = "Function"
+      func = "CaptureAristaInventory"
+    }
+    action {
+      type = "SendLine"
+      line = "show environment power | no-more"
+    }
+  }
+}
+
+state "AristaEnvironmentPowerOutput" {
+  transition {
+    target = "AristaEnvironmentCoolingOutput"
     trigger {
       type   = "string"
       string = "localhost#"
     }
+    action {
+      type = "Function"
+      func = "CaptureAristaEnvironmentPower"
+    }
+    action {
+      type = "SendLine"
+      line = "show environment cooling | no-more"
+    }
+  }
+}
+
+state "AristaEnvironmentCoolingOutput" {
+  transition {
+    target = "HookAristaVerifyWipe"
+    trigger {
+      type   = "string"
+      string = "localhost#"
+    }
+    action {
+      type = "Function"
+      func = "CaptureAristaEnvironmentCooling"
+    }
+  }
+}
+
+state "HookAristaVerifyWipe" {
+  transition {
+    target = "HookAristaCLI"
+    trigger {
+      type = "immediate"
+    }
   }
 }
 
@@ -0,0 +1,220 @@
+id = "icx_wipe"
+
+state "SwitchDetect" {
+  merge = "append"
+  transition {
+    target = "IcxWaitForBootMonitor"
+    trigger {
+      type   = "string"
+      string = "Boot Code Version"
+    }
+    action {
+      type   = "AddDeviceInfo"
+      Vendor = "Ruckus"
+    }
+  }
+}
+
+state "IcxWaitForBootMonitor" {
+  transition {
+    target = "IcxBootMonitor"
+    trigger {
+      type   = "string"
+      string = "Hit b to stop autoboot"
+    }
+    action {
+      type = "Function"
+      func = "CaptureIcxBootCodeVersion"
+    }
+    action {
+      type = "Send"
+      text = "b"
+    }
+    action {
+      type = "Flush"
+    }
+  }
+}
+
+state "IcxBootMonitor" {
+  transition {
+    target = "IcxFactoryDefaultConfirm"
+    trigger {
+      type  = "regex"
+      regex = "ICX[0-9]+[A-Za-z0-9\\-]*-Boot>"
+    }
+    action {
+      type = "SendLine"
+      line = "factory set-default"
+    }
+  }
+}
+
+state "IcxFactoryDefaultConfirm" {
+  transition {
+    target = "IcxFactoryDefaultDone"
+    trigger {
+      type   = "string"
+      string = "(enter 'y' or 'n')"
+    }
+    action {
+      type = "SendLine"
+      line = "y"
+    }
+  }
+}
+
+state "IcxFactoryDefaultDone" {
+  transition {
+    target = "IcxAwaitBoot"
+    trigger {
+      type  = "regex"
+      regex = "ICX[0-9]+[A-Za-z0-9\\-]*-Boot>"
+    }
+    action {
+      type = "SendLine"
+      line = "boot_primary"
+    }
+  }
+}
+
+state "IcxAwaitBoot" {
+  transition {
+    target = "IcxAwaitBoot"
+    trigger {
+      type   = "string"
+      string = "Press Enter key to login"
+    }
+    action {
+      type = "SendLine"
+      line = ""
+    }
+  }
+  transition {
+    target = "IcxAwaitBoot"
+    trigger {
+      type   = "string"
+      string = "Please Enter Login Name:"
+    }
+    action {
+      type = "SendLine"
+      line = "super"
+    }
+  }
+  transition {
+    target = "IcxAwaitBoot"
+    trigger {
+      type   = "string"
+      string = "Please Enter Password:"
+    }
+    action {
+      type = "SendLine"
+      line = "sp-admin"
+    }
+  }
+  # The factory default sp-admin has to be replaced on the first login, with icx_password from the JobConfig.
+  transition {
+    target = "IcxAwaitBoot"
+    trigger {
+      type   = "regex"
+      regex  = "Enter the (?:new|reconfirm) password for user super"
+    }
+    action {
+      type = "SendConfigValue"
+      key  = "icx_password"
+    }
+    action {
+      type = "SendLine"
+      line = ""
+    }
+  }
+  transition {
+    target = "IcxEnable"
+    trigger {
+      type  = "regex"
+      regex = "ICX[0-9]+[A-Za-z0-9\\-]*(?: Router| Switch)?>"
+    }
+    action {
+      type = "SendLine"
+      line = "enable"
+    }
+  }
+}
+
+state "IcxEnable" {
+  transition {
+    target = "IcxSkipPaging"
+    trigger {
+      type  = "regex"
+      regex = "ICX[0-9]+[A-Za-z0-9\\-]*(?: Router| Switch)?#"
+    }
+    action {
+      type = "SendLine"
+      line = "skip-page-display"
+    }
+  }
+}
+
+state "IcxSkipPaging" {
+  transition {
+    target = "IcxEraseStartupConfig"
+    trigger {
+      type  = "regex"
+      regex = "ICX[0-9]+[A-Za-z0-9\\-]*(?: Router| Switch)?#"
+    }
+    action {
+      type = "SendLine"
+      line = "erase startup-config"
+    }
+  }
+}
+
+state "IcxEraseStartupConfig" {
+  transition {
+    target = "IcxVersionOutput"
+    trigger {
+      type  = "regex"
+      regex = "ICX[0-9]+[A-Za-z0-9\\-]*(?: Router| Switch)?#"
+    }
+    action {
+      type = "SendLine"
+      line = "show version"
+    }
+  }
+}
+
+state "IcxVersionOutput" {
+  transition {
+    target = "HookIcxVerifyWipe"
+    trigger {
+      type  = "regex"
+      regex = "ICX[0-9]+[A-Za-z0-9\\-]*(?: Router| Switch)?#"
+    }
+    action {
+      type = "Function"
+      func = "CaptureIcxVersion"
+    }
+  }
+}
+
+state "HookIcxVerifyWipe" {
+  transition {
+    target = "HookIcxCLI"
+    trigger {
+      type = "immediate"
+    }
+  }
+}
+
+state "HookIcxCLI" {
+  transition {
+    target = "EndJob"
+    trigger {
+      type = "immediate"
+    }
+    action {
+      type = "SendLine"
+      line = "exit"
+    }
+  }
+}
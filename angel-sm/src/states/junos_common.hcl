@@ -316,7 +316,7 @@ state "JunosVersionOutput" {
 
 state "JunosChassisOutput" {
   transition {
-    target = "HookJunosCLI"
+    target = "HookJunosVerifyWipe"
     trigger {
       type  = "regex"
       regex = "root(@[A-Za-z0-9\\-]+)?>"
@@ -329,6 +329,15 @@ state "JunosChassisOutput" {
   }
 }
 
+state "HookJunosVerifyWipe" {
+  transition {
+    target = "HookJunosCLI"
+    trigger {
+      type = "immediate"
+    }
+  }
+}
+
 state "HookJunosCLI" {
   transition {
     target = "JunosPoweroff"
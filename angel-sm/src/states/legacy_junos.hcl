@@ -176,6 +176,10 @@ state "LegacyJunosAwaitRecoveryShell" {
       type     = "Delay"
       duration = 3
     }
+    action {
+      type = "Function"
+      func = "CaptureJunosInventory"
+    }
     action {
       type = "SendLine"
       line = "request system zeroize"
@@ -0,0 +1,275 @@
+id = "mikrotik_wipe"
+
+state "SwitchDetect" {
+  merge = "append"
+  transition {
+    target = "MikrotikRouterBoot"
+    trigger {
+      type   = "string"
+      string = "RouterBOOT booter"
+    }
+    action {
+      type   = "AddDeviceInfo"
+      Vendor = "MikroTik"
+    }
+  }
+  transition {
+    target = "MikrotikLogin"
+    trigger {
+      type  = "regex"
+      regex = "[A-Za-z0-9\\-\\.]+ Login:"
+    }
+    action {
+      type   = "AddDeviceInfo"
+      Vendor = "MikroTik"
+    }
+    action {
+      type = "SendLine"
+      line = "admin+ct"
+    }
+  }
+}
+
+state "MikrotikRouterBoot" {
+  transition {
+    target = "MikrotikBootMenu"
+    trigger {
+      type   = "string"
+      string = "Press any key within"
+    }
+    action {
+      type = "Function"
+      func = "CaptureMikrotikRouterBoot"
+    }
+    # Interrupt the boot to get into the RouterBOOT setup menu.
+    action {
+      type = "Send"
+      text = " "
+    }
+    action {
+      type = "Flush"
+    }
+  }
+}
+
+state "MikrotikBootMenu" {
+  transition {
+    target = "MikrotikBoardInfo"
+    trigger {
+      type   = "string"
+      string = "your choice:"
+    }
+    action {
+      type = "Send"
+      text = "i"
+    }
+    action {
+      type = "Flush"
+    }
+  }
+}
+
+state "MikrotikBoardInfo" {
+  transition {
+    target = "MikrotikBootMenuReset"
+    trigger {
+      type   = "string"
+      string = "your choice:"
+    }
+    action {
+      type = "Function"
+      func = "CaptureMikrotikBoardInfo"
+    }
+    # Reset the booter settings, so RouterOS boots from its own storage again.
+    action {
+      type = "Send"
+      text = "r"
+    }
+    action {
+      type = "Flush"
+    }
+  }
+}
+
+state "MikrotikBootMenuReset" {
+  transition {
+    target = "MikrotikBootMenuReset"
+    trigger {
+      type  = "regex"
+      regex = "(?i)(?:reset|do you want).*\\[y/N\\]"
+    }
+    action {
+      type = "Send"
+      text = "y"
+    }
+    action {
+      type = "Flush"
+    }
+  }
+  transition {
+    target = "MikrotikAwaitLogin"
+    trigger {
+      type   = "string"
+      string = "your choice:"
+    }
+    # Leave the setup menu, the boot carries on into RouterOS.
+    action {
+      type = "Send"
+      text = "x"
+    }
+    action {
+      type = "Flush"
+    }
+  }
+}
+
+state "MikrotikAwaitLogin" {
+  transition {
+    target = "MikrotikLogin"
+    trigger {
+      type  = "regex"
+      regex = "[A-Za-z0-9\\-\\.]+ Login:"
+    }
+    action {
+      type = "SendLine"
+      line = "admin+ct"
+    }
+  }
+}
+
+state "MikrotikLogin" {
+  transition {
+    target = "MikrotikLogin"
+    trigger {
+      type   = "string"
+      string = "Password:"
+    }
+    action {
+      type = "SendLine"
+      line = ""
+    }
+  }
+  transition {
+    target = "EndJob"
+    trigger {
+      type   = "string"
+      string = "Login failed, incorrect username or password"
+    }
+    action {
+      type = "AddDeviceInfo"
+      flag = "Aborted"
+    }
+  }
+  transition {
+    target = "MikrotikLogin"
+    trigger {
+      type   = "string"
+      string = "Do you want to see the software license?"
+    }
+    action {
+      type = "SendLine"
+      line = "n"
+    }
+  }
+  transition {
+    target = "MikrotikLogin"
+    trigger {
+      type  = "regex"
+      regex = "(?:repeat )?new password>"
+    }
+    action {
+      type = "SendLine"
+      line = "Password123!"
+    }
+  }
+  transition {
+    target = "MikrotikRouterboardOutput"
+    trigger {
+      type  = "regex"
+      regex = "\\[admin@[^\\]]+\\] >"
+    }
+    action {
+      type = "SendLine"
+      line = "/system routerboard print"
+    }
+  }
+}
+
+state "MikrotikRouterboardOutput" {
+  transition {
+    target = "MikrotikResourceOutput"
+    trigger {
+      type  = "regex"
+      regex = "\\[admin@[^\\]]+\\] >"
+    }
+    action {
+      type = "Function"
+      func = "CaptureMikrotikSystemInfo"
+    }
+    action {
+      type = "SendLine"
+      line = "/system resource print"
+    }
+  }
+}
+
+state "MikrotikResourceOutput" {
+  transition {
+    target = "MikrotikResetConfiguration"
+    trigger {
+      type  = "regex"
+      regex = "\\[admin@[^\\]]+\\] >"
+    }
+    action {
+      type = "Function"
+      func = "CaptureMikrotikSystemInfo"
+    }
+    action {
+      type = "SendLine"
+      line = "/system reset-configuration no-defaults=yes skip-backup=yes"
+    }
+  }
+}
+
+state "MikrotikResetConfiguration" {
+  transition {
+    target = "MikrotikAwaitReset"
+    trigger {
+      type   = "string"
+      string = "Reset anyway? [y/N]"
+    }
+    action {
+      type = "Send"
+      text = "y"
+    }
+    action {
+      type = "Flush"
+    }
+  }
+}
+
+state "MikrotikAwaitReset" {
+  transition {
+    target = "HookMikrotikVerifyWipe"
+    trigger {
+      type   = "string"
+      string = "Rebooting..."
+    }
+  }
+  transition {
+    target = "HookMikrotikVerifyWipe"
+    trigger {
+      type   = "string"
+      string = "RouterBOOT booter"
+    }
+  }
+}
+
+state "HookMikrotikVerifyWipe" {
+  transition {
+    target = "EndJob"
+    trigger {
+      type = "immediate"
+    }
+  }
+}
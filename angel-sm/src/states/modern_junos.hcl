@@ -247,6 +247,10 @@ state "ModernJunosEvo4" {
       type  = "regex"
       regex = "root(@[A-Za-z0-9\\-]+)?>"
     }
+    action {
+      type = "Function"
+      func = "CaptureJunosInventory"
+    }
     action {
       type = "SendLine"
       line = "request system zeroize"
@@ -345,6 +349,10 @@ state "ModernJunosAwaitRecoveryShell" {
       type = "regex"
       regex = "\\{[a-z0-9]+:0\\}"
     }
+    action {
+      type = "Function"
+      func = "CaptureJunosInventory"
+    }
     action {
       type = "SendLine"
       line = "request system zeroize"
@@ -356,6 +364,10 @@ state "ModernJunosAwaitRecoveryShell" {
       type = "string"
       string = "root>"
     }
+    action {
+      type = "Function"
+      func = "CaptureJunosInventory"
+    }
     action {
       type = "SendLine"
       line = "request system zeroize"
@@ -0,0 +1,444 @@
+id = "verify_wipe"
+
+depends = [
+  "wipe",
+]
+
+# After the wipe, log out and back in with the factory credentials, then check that nothing
+# of the previous configuration survived before handing over to the CLI hooks.
+# The echo of a long command may be redrawn when it is wider than the terminal, so only its
+# tail is matched to skip past it.
+
+state "HookJunosVerifyWipe" {
+  transition {
+    target = "JunosVerifyLogout"
+    trigger {
+      type = "immediate"
+    }
+    action {
+      type = "SendLine"
+      line = "exit"
+    }
+  }
+}
+
+state "JunosVerifyLogout" {
+  transition {
+    target = "JunosVerifyLogin"
+    trigger {
+      type  = "regex"
+      regex = "root@(?:[A-Za-z0-9\\-]*:[A-Z]+:0%|:~)"
+    }
+    action {
+      type = "SendLine"
+      line = "exit"
+    }
+  }
+}
+
+state "JunosVerifyLogin" {
+  transition {
+    target = "JunosVerifyLogin"
+    trigger {
+      type   = "string"
+      string = "login:"
+    }
+    action {
+      type = "SendLine"
+      line = "root"
+    }
+  }
+  transition {
+    target = "EndJob"
+    trigger {
+      type   = "string"
+      string = "Password:"
+    }
+    action {
+      type = "AddDeviceInfo"
+      flag = "DidNotWipe"
+    }
+  }
+  transition {
+    target = "JunosVerifyCli"
+    trigger {
+      type  = "regex"
+      regex = "root@(?:[A-Za-z0-9\\-]*:[A-Z]+:0%|:~)"
+    }
+    action {
+      type = "SendLine"
+      line = "cli"
+    }
+  }
+}
+
+state "JunosVerifyCli" {
+  transition {
+    target = "JunosVerifyCli"
+    trigger {
+      type   = "string"
+      string = "Retry connection attempts ? [yes,no] (yes)"
+    }
+    action {
+      type = "SendLine"
+      line = "yes"
+    }
+  }
+  transition {
+    target = "JunosVerifyWipeOutput"
+    trigger {
+      type  = "regex"
+      regex = "root(@[A-Za-z0-9\\-]+)?>"
+    }
+    action {
+      type = "SendLine"
+      line = "show configuration | display set | match \"host-name|root-authentication|login user|snmp community|name-server|routing-options static\" | count"
+    }
+  }
+}
+
+state "JunosVerifyWipeOutput" {
+  transition {
+    target = "HookJunosCLI"
+    trigger {
+      type  = "regex"
+      regex = "root(@[A-Za-z0-9\\-]+)?>"
+    }
+    action {
+      type = "Function"
+      func = "CheckJunosResidualConfig"
+    }
+  }
+}
+
+state "HookAristaVerifyWipe" {
+  transition {
+    target = "AristaVerifyLogin"
+    trigger {
+      type = "immediate"
+    }
+    action {
+      type = "SendLine"
+      line = "exit"
+    }
+  }
+}
+
+state "AristaVerifyLogin" {
+  transition {
+    target = "AristaVerifyLogin"
+    trigger {
+      type   = "string"
+      string = "login:"
+    }
+    action {
+      type = "SendLine"
+      line = "admin"
+    }
+  }
+  transition {
+    target = "EndJob"
+    trigger {
+      type   = "string"
+      string = "Password:"
+    }
+    action {
+      type = "AddDeviceInfo"
+      flag = "DidNotWipe"
+    }
+  }
+  transition {
+    target = "AristaVerifyEnable"
+    trigger {
+      type   = "string"
+      string = "localhost>"
+    }
+    action {
+      type = "SendLine"
+      line = "enable"
+    }
+  }
+}
+
+state "AristaVerifyEnable" {
+  transition {
+    target = "AristaVerifyWipeEcho"
+    trigger {
+      type   = "string"
+      string = "localhost#"
+    }
+    action {
+      type = "SendLine"
+      line = "show running-config | include ^hostname|secret|snmp-server community|ip route|ip address [0-9]"
+    }
+  }
+}
+
+state "AristaVerifyWipeEcho" {
+  transition {
+    target = "AristaVerifyWipeOutput"
+    trigger {
+      type   = "string"
+      string = "|ip address [0-9]"
+    }
+  }
+}
+
+state "AristaVerifyWipeOutput" {
+  transition {
+    target = "HookAristaCLI"
+    trigger {
+      type   = "string"
+      string = "localhost#"
+    }
+    action {
+      type = "Function"
+      func = "CheckResidualConfig"
+    }
+  }
+}
+
+state "HookIcxVerifyWipe" {
+  transition {
+    target = "IcxVerifyLogout"
+    trigger {
+      type = "immediate"
+    }
+    action {
+      type = "SendLine"
+      line = "exit"
+    }
+  }
+}
+
+state "IcxVerifyLogout" {
+  transition {
+    target = "IcxVerifyLogin"
+    trigger {
+      type  = "regex"
+      regex = "ICX[0-9]+[A-Za-z0-9\\-]*(?: Router| Switch)?>"
+    }
+    action {
+      type = "SendLine"
+      line = "exit"
+    }
+  }
+}
+
+# The factory super account had to pick a new password on the first login, so ICX cannot be verified
+# with the true factory default sp-admin. It logs in with the icx_password from the JobConfig that the
+# wipe set instead.
+state "IcxVerifyLogin" {
+  transition {
+    target = "IcxVerifyLogin"
+    trigger {
+      type   = "string"
+      string = "Press Enter key to login"
+    }
+    action {
+      type = "SendLine"
+      line = ""
+    }
+  }
+  transition {
+    target = "IcxVerifyLogin"
+    trigger {
+      type   = "string"
+      string = "Please Enter Login Name:"
+    }
+    action {
+      type = "SendLine"
+      line = "super"
+    }
+  }
+  transition {
+    target = "IcxVerifyLogin"
+    trigger {
+      type   = "string"
+      string = "Please Enter Password:"
+    }
+    action {
+      type = "SendConfigValue"
+      key  = "icx_password"
+    }
+    action {
+      type = "SendLine"
+      line = ""
+    }
+  }
+  transition {
+    target = "EndJob"
+    trigger {
+      type  = "regex"
+      regex = "(?i)(?:incorrect (?:user ?name|password)|login failed)"
+    }
+    action {
+      type = "AddDeviceInfo"
+      flag = "DidNotWipe"
+    }
+  }
+  transition {
+    target = "IcxVerifyEnable"
+    trigger {
+      type  = "regex"
+      regex = "ICX[0-9]+[A-Za-z0-9\\-]*(?: Router| Switch)?>"
+    }
+    action {
+      type = "SendLine"
+      line = "enable"
+    }
+  }
+}
+
+state "IcxVerifyEnable" {
+  transition {
+    target = "IcxVerifySkipPaging"
+    trigger {
+      type  = "regex"
+      regex = "ICX[0-9]+[A-Za-z0-9\\-]*(?: Router| Switch)?#"
+    }
+    action {
+      type = "SendLine"
+      line = "skip-page-display"
+    }
+  }
+}
+
+state "IcxVerifySkipPaging" {
+  transition {
+    target = "IcxVerifyWipeEcho"
+    trigger {
+      type  = "regex"
+      regex = "ICX[0-9]+[A-Za-z0-9\\-]*(?: Router| Switch)?#"
+    }
+    action {
+      type = "SendLine"
+      line = "show running-config | include hostname|snmp-server community|ip route|logging host"
+    }
+  }
+}
+
+state "IcxVerifyWipeEcho" {
+  transition {
+    target = "IcxVerifyWipeOutput"
+    trigger {
+      type   = "string"
+      string = "|logging host"
+    }
+  }
+}
+
+state "IcxVerifyWipeOutput" {
+  transition {
+    target = "HookIcxCLI"
+    trigger {
+      type  = "regex"
+      regex = "ICX[0-9]+[A-Za-z0-9\\-]*(?: Router| Switch)?#"
+    }
+    action {
+      type = "Function"
+      func = "CheckResidualConfig"
+    }
+  }
+}
+
+# RouterOS reboots after the reset, so log in once more and look at the export.
+state "HookMikrotikVerifyWipe" {
+  transition {
+    target = "MikrotikVerifyLogin"
+    trigger {
+      type  = "regex"
+      regex = "[A-Za-z0-9\\-\\.]+ Login:"
+    }
+    action {
+      type = "SendLine"
+      line = "admin+ct"
+    }
+  }
+}
+
+state "MikrotikVerifyLogin" {
+  transition {
+    target = "MikrotikVerifyLogin"
+    trigger {
+      type   = "string"
+      string = "Password:"
+    }
+    action {
+      type = "SendLine"
+      line = ""
+    }
+  }
+  transition {
+    target = "EndJob"
+    trigger {
+      type   = "string"
+      string = "Login failed, incorrect username or password"
+    }
+    action {
+      type = "AddDeviceInfo"
+      flag = "DidNotWipe"
+    }
+  }
+  transition {
+    target = "MikrotikVerifyLogin"
+    trigger {
+      type   = "string"
+      string = "Do you want to see the software license?"
+    }
+    action {
+      type = "SendLine"
+      line = "n"
+    }
+  }
+  transition {
+    target = "MikrotikVerifyLogin"
+    trigger {
+      type  = "regex"
+      regex = "(?:repeat )?new password>"
+    }
+    action {
+      type = "SendLine"
+      line = "Password123!"
+    }
+  }
+  transition {
+    target = "MikrotikVerifyExportEcho"
+    trigger {
+      type  = "regex"
+      regex = "\\[admin@[^\\]]+\\] >"
+    }
+    action {
+      type = "SendLine"
+      line = "/export terse"
+    }
+  }
+}
+
+state "MikrotikVerifyExportEcho" {
+  transition {
+    target = "MikrotikVerifyExportOutput"
+    trigger {
+      type   = "string"
+      string = "export terse"
+    }
+  }
+}
+
+state "MikrotikVerifyExportOutput" {
+  transition {
+    target = "EndJob"
+    trigger {
+      type  = "regex"
+      regex = "\\[admin@[^\\]]+\\] >"
+    }
+    action {
+      type = "Function"
+      func = "CheckResidualConfig"
+    }
+    action {
+      type = "SendLine"
+      line = "/quit"
+    }
+  }
+}
@@ -7,4 +7,6 @@ depends = [
   "arista_wipe",
   "aruba_wipe",
   "hp_wipe",
+  "icx_wipe",
+  "mikrotik_wipe",
 ]
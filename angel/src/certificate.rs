@@ -0,0 +1,18 @@
+use color_eyre::eyre::Context;
+use ed25519_dalek::SigningKey;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+pub async fn load_signing_key<P: AsRef<Path>>(path: P) -> color_eyre::Result<SigningKey> {
+    let data = tokio::fs::read_to_string(path.as_ref())
+        .await
+        .wrap_err_with(|| format!("failed to read certificate key {:?}", path.as_ref()))?;
+    let mut secret = [0u8; 32];
+    hex::decode_to_slice(data.trim(), &mut secret).wrap_err("invalid certificate key")?;
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+pub async fn hash_file<P: AsRef<Path>>(path: P) -> color_eyre::Result<String> {
+    let data = tokio::fs::read(path).await?;
+    Ok(hex::encode(Sha256::digest(&data)))
+}
@@ -0,0 +1,112 @@
+use crate::logging::TracingTarget;
+use chrono::{DateTime, Utc};
+use cthulhu_angel_sm::action::Action;
+use cthulhu_angel_sm::data_structure::StateMachineTrigger;
+use serde::Serialize;
+use std::io::Write;
+use tracing::warn;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// One line of `<job>.events.jsonl`.
+#[derive(Serialize)]
+pub struct JobEventRecord<'a> {
+    pub time: DateTime<Utc>,
+    /// The state the job is in once the event happened.
+    pub state: &'a str,
+    /// Bytes read from the device so far, that is the size of the raw log at the time.
+    /// A match ends at or shortly before this offset.
+    pub raw_log_offset: Option<u64>,
+    #[serde(flatten)]
+    pub event: JobEvent<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JobEvent<'a> {
+    Transition {
+        from: &'a str,
+        trigger: &'a StateMachineTrigger,
+        /// The text the trigger matched, empty for immediate transitions.
+        matched: &'a str,
+    },
+    Action {
+        /// The action as written in the state file.
+        action: &'a Action,
+        duration_ms: u64,
+        result: ActionResult,
+        error: Option<String>,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionResult {
+    Ok,
+    Error,
+}
+
+/// Append an event, a broken event log must not stop the job.
+pub fn write_event(target: &TracingTarget, record: &JobEventRecord) {
+    let mut line = match serde_json::to_vec(record) {
+        Ok(line) => line,
+        Err(e) => {
+            warn!("Unable to serialize job event: {e}");
+            return;
+        }
+    };
+    line.push(b'\n');
+    if let Err(e) = target.make_writer().write_all(&line) {
+        warn!("Unable to write job event: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn events_file() {
+        let path = std::env::temp_dir().join(format!("cthulhu-events-{}.jsonl", std::process::id()));
+        let target = TracingTarget::default();
+        target.open_file(&path).unwrap();
+
+        let trigger = StateMachineTrigger::Immediate;
+        write_event(&target, &JobEventRecord {
+            time: Utc::now(),
+            state: "Login",
+            raw_log_offset: Some(12),
+            event: JobEvent::Transition { from: "Init", trigger: &trigger, matched: "" },
+        });
+        let action = Action::Delay { duration: Duration::from_millis(1500) };
+        let error = color_eyre::eyre::eyre!("gone");
+        write_event(&target, &JobEventRecord {
+            time: Utc::now(),
+            state: "Login",
+            raw_log_offset: None,
+            event: JobEvent::Action {
+                action: &action,
+                duration_ms: 1500,
+                result: ActionResult::Error,
+                error: Some(format!("{error:#}")),
+            },
+        });
+        target.close().unwrap();
+
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["event"], "transition");
+        assert_eq!(lines[0]["from"], "Init");
+        assert_eq!(lines[0]["state"], "Login");
+        assert_eq!(lines[0]["raw_log_offset"], 12);
+        assert_eq!(lines[1]["event"], "action");
+        assert_eq!(lines[1]["action"], serde_json::json!({"type": "Delay", "duration": 1.5}));
+        assert_eq!(lines[1]["result"], "error");
+        assert_eq!(lines[1]["error"], "gone");
+    }
+}
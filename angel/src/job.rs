@@ -1,29 +1,92 @@
-use crate::logging::TracingTarget;
+use crate::certificate::hash_file;
+use crate::events::{ActionResult, JobEvent, JobEventRecord, write_event};
+use crate::logging::{CastTarget, TracingTarget};
 use crate::mqtt::MQTTSender;
-use chrono::Utc;
+use crate::ports::reconnect::PortControl;
+use crate::retention::{LogIndexEntry, archive_job};
+use chrono::{DateTime, TimeDelta, Utc};
 use color_eyre::eyre::Context;
 use cthulhu_angel_sm::AngelJob;
+use cthulhu_angel_sm::action::Action;
+use cthulhu_angel_sm::builder::StateMachineBuilder;
 use cthulhu_angel_sm::data_structure::{State, StateMachineTransition, StateMachineTrigger};
 use cthulhu_angel_sm::state::StateMachine;
-use cthulhu_common::devinfo::DeviceInformation;
-use cthulhu_common::job::JobData;
-use cthulhu_common::status::JobUpdate;
+use cthulhu_common::certificate::{ErasureCertificate, sign_certificate};
+use cthulhu_common::devinfo::{DeviceInformation, SeverityPolicy};
+use cthulhu_common::job::{JobData, JobStatus};
+use cthulhu_common::status::{AngelStatus, JobUpdate, OperatorAction, RejectedActiveStates, Takeover};
+use cthulhu_config::angel::{LogRetentionConfig, WatchdogConfig};
+use ed25519_dalek::SigningKey;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use swexpect::SwitchExpect;
 use swexpect::hay::ReadUntil;
 use tracing::{debug, info, warn};
 
+/// Everything needed to pick a job up again after the angel restarts, kept in `log_dir`.
+#[derive(Serialize, Deserialize)]
+struct JobSnapshot {
+    data: JobData,
+    current_state: State,
+    tracing_log_file: Option<PathBuf>,
+    raw_log_file: Option<PathBuf>,
+    #[serde(default)]
+    events_log_file: Option<PathBuf>,
+    #[serde(default)]
+    cast_file: Option<PathBuf>,
+    /// The state files from the config when the snapshot was taken.
+    #[serde(default)]
+    configured_active_states: Vec<String>,
+}
+
+/// A transition whose trigger has matched, see [`ActiveJob::wait_for_trigger`].
+pub struct Triggered {
+    transition: StateMachineTransition,
+    data: String,
+    matched: String,
+}
+
 pub struct ActiveJob {
     pub data: JobData,
-    state_machine: StateMachine,
-    shutdown_requested: bool,
+    state_machine: Arc<StateMachine>,
+    /// Built for `data.next_active_states`, takes over at the next reset.
+    next_state_machine: Option<Arc<StateMachine>>,
+    configured_active_states: Vec<String>,
     current_state: State,
     pub mqtt: MQTTSender,
     tracing_target: TracingTarget,
     rawlog_target: TracingTarget,
+    events_target: TracingTarget,
+    cast_target: CastTarget,
     log_dir: Option<PathBuf>,
+    tracing_log_file: Option<PathBuf>,
+    raw_log_file: Option<PathBuf>,
+    events_log_file: Option<PathBuf>,
+    cast_file: Option<PathBuf>,
+    resume_jobs: bool,
+    log_retention: LogRetentionConfig,
+    certificate_key: Option<SigningKey>,
+    severity_policy: SeverityPolicy,
     job_config: BTreeMap<String, String>,
+    port_control: PortControl,
+}
+
+/// The built-in state machine with the given state files activated.
+pub fn build_state_machine(active_states: &[String]) -> color_eyre::Result<Arc<StateMachine>> {
+    let mut smb = StateMachineBuilder::new();
+    smb.load_builtin_state_files()?;
+    for id in active_states.iter() {
+        smb.activate_state_file(id)?;
+    }
+    Ok(Arc::new(smb.build()?))
+}
+
+fn certificate_file(raw_log_file: &Path) -> PathBuf {
+    raw_log_file.with_extension("").with_extension("certificate.json")
 }
 
 impl AngelJob for ActiveJob {
@@ -36,7 +99,8 @@ impl AngelJob for ActiveJob {
                     self.data.job_started.unwrap_or(Utc::now()).format("%Y-%m-%d--%H:%M:%S"),
                     self.mqtt.id()
                 ));
-                self.tracing_target.open_file(tracing_log_file)?;
+                self.tracing_target.open_file(&tracing_log_file)?;
+                self.tracing_log_file = Some(tracing_log_file);
             }
             {
                 let mut raw_log_file = log_dir.clone();
@@ -45,7 +109,28 @@ impl AngelJob for ActiveJob {
                     self.data.job_started.unwrap_or(Utc::now()).format("%Y-%m-%d--%H:%M:%S"),
                     self.mqtt.id()
                 ));
-                self.rawlog_target.open_file(raw_log_file)?;
+                self.rawlog_target.open_file(&raw_log_file)?;
+                self.raw_log_file = Some(raw_log_file);
+            }
+            {
+                let mut events_log_file = log_dir.clone();
+                events_log_file.push(format!(
+                    "{}--{}.events.jsonl",
+                    self.data.job_started.unwrap_or(Utc::now()).format("%Y-%m-%d--%H:%M:%S"),
+                    self.mqtt.id()
+                ));
+                self.events_target.open_file(&events_log_file)?;
+                self.events_log_file = Some(events_log_file);
+            }
+            {
+                let mut cast_file = log_dir.clone();
+                cast_file.push(format!(
+                    "{}--{}.cast",
+                    self.data.job_started.unwrap_or(Utc::now()).format("%Y-%m-%d--%H:%M:%S"),
+                    self.mqtt.id()
+                ));
+                self.cast_target.open_file(&cast_file, self.mqtt.id())?;
+                self.cast_file = Some(cast_file);
             }
         }
         info!("Job initialized!");
@@ -58,7 +143,12 @@ impl AngelJob for ActiveJob {
         for i in self.data.info_items.iter() {
             info!(" - {i:?}");
         }
-        self.send_update(JobUpdate::JobEnd(Utc::now())).await?;
+        let job_ended = Utc::now();
+        self.issue_certificate(job_ended).await?;
+        self.send_update(JobUpdate::JobEnd(job_ended)).await?;
+        if let Err(e) = self.archive_logs(job_ended).await {
+            warn!("Archiving the job logs failed: {e:?}");
+        }
         Ok(())
     }
 
@@ -66,8 +156,21 @@ impl AngelJob for ActiveJob {
         info!("Resetting job...");
         //TODO: Maybe send a JobEnd sometimes?
 
+        // An aborted job never got to finish_job, its logs still need to go to retention.
+        if self.has_unarchived_logs()
+            && let Err(e) = self.archive_logs(Utc::now()).await
+        {
+            warn!("Archiving the job logs failed: {e:?}");
+        }
+
         self.current_state = "Init".to_string();
         self.data.reset();
+        if let Some(state_machine) = self.next_state_machine.take() {
+            self.state_machine = state_machine;
+            let current = self.data.next_active_states.take().unwrap_or_default();
+            info!("Switched to state files {current:?}.");
+            self.send_update(JobUpdate::ActiveStates { current, next: None }).await?;
+        }
         self.send_update(JobUpdate::JobStart(Utc::now())).await?;
         self.send_update(JobUpdate::JobStageTransition(
             Utc::now(),
@@ -87,30 +190,282 @@ impl AngelJob for ActiveJob {
     async fn get_job_config_key(&self, key: &str) -> Option<String> {
         self.job_config.get(key).cloned()
     }
+
+    async fn send_break(&mut self, duration: Duration) -> color_eyre::Result<()> {
+        self.port_control.send_break(duration).await
+    }
+
+    async fn action_performed(
+        &mut self,
+        action: &Action,
+        elapsed: Duration,
+        error: Option<&color_eyre::Report>,
+    ) {
+        self.record_event(JobEvent::Action {
+            action,
+            duration_ms: elapsed.as_millis() as u64,
+            result: if error.is_some() {
+                ActionResult::Error
+            } else {
+                ActionResult::Ok
+            },
+            error: error.map(|e| format!("{e:#}")),
+        });
+    }
 }
 
 impl ActiveJob {
+    #[allow(clippy::too_many_arguments)]
     pub fn create(
         mqtt: MQTTSender,
         log_dir: Option<PathBuf>,
         tracing_target: TracingTarget,
         rawlog_target: TracingTarget,
-        state_machine: StateMachine,
+        cast_target: CastTarget,
+        active_states: Vec<String>,
+        state_machine: Arc<StateMachine>,
         job_config: BTreeMap<String, String>,
+        certificate_key: Option<SigningKey>,
+        severity_policy: SeverityPolicy,
+        resume_jobs: bool,
+        log_retention: LogRetentionConfig,
+        port_control: PortControl,
     ) -> Self {
         Self {
-            data: JobData::with_label(mqtt.id()),
+            data: JobData {
+                active_states: active_states.clone(),
+                ..JobData::with_label(mqtt.id())
+            },
+            configured_active_states: active_states,
             current_state: "Init".to_string(),
             mqtt,
             log_dir,
+            tracing_log_file: None,
+            raw_log_file: None,
+            events_log_file: None,
+            cast_file: None,
+            resume_jobs,
+            log_retention,
+            certificate_key,
+            severity_policy,
             tracing_target,
             rawlog_target,
+            events_target: TracingTarget::default(),
+            cast_target,
             state_machine,
+            next_state_machine: None,
             job_config,
-            shutdown_requested: false,
+            port_control,
         }
     }
 
+    fn snapshot_file(&self) -> Option<PathBuf> {
+        self.log_dir
+            .as_ref()
+            .map(|d| d.join(format!("{}.job.json", self.mqtt.id())))
+    }
+
+    async fn save_snapshot(&self) -> color_eyre::Result<()> {
+        let Some(snapshot_file) = self.snapshot_file() else {
+            return Ok(());
+        };
+        let snapshot = JobSnapshot {
+            data: self.data.clone(),
+            current_state: self.current_state.clone(),
+            tracing_log_file: self.tracing_log_file.clone(),
+            raw_log_file: self.raw_log_file.clone(),
+            events_log_file: self.events_log_file.clone(),
+            cast_file: self.cast_file.clone(),
+            configured_active_states: self.configured_active_states.clone(),
+        };
+        // Write and rename, so a crash never leaves half a snapshot behind.
+        let tmp_file = snapshot_file.with_extension("json.tmp");
+        tokio::fs::create_dir_all(snapshot_file.parent().unwrap_or(&snapshot_file)).await?;
+        tokio::fs::write(&tmp_file, serde_json::to_vec(&snapshot)?).await?;
+        tokio::fs::rename(&tmp_file, &snapshot_file).await?;
+        Ok(())
+    }
+
+    async fn load_snapshot(&self) -> color_eyre::Result<Option<JobSnapshot>> {
+        let Some(snapshot_file) = self.snapshot_file() else {
+            return Ok(None);
+        };
+        if !tokio::fs::try_exists(&snapshot_file).await? {
+            return Ok(None);
+        }
+        let data = tokio::fs::read(&snapshot_file).await?;
+        Ok(Some(serde_json::from_slice(&data)?))
+    }
+
+    /// Start up from the snapshot of the previous run.
+    ///
+    /// With `resume_jobs` an interrupted job continues in the state it was in. Otherwise, the
+    /// previous job is republished, and ended as aborted if it was still running, so that heaven
+    /// and netbox keep its result.
+    pub async fn resume_or_reset(&mut self) -> color_eyre::Result<()> {
+        let mut snapshot = match self.load_snapshot().await {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                warn!("Ignoring unreadable job snapshot: {e:?}");
+                None
+            }
+        };
+        // Heaven may still have us down as going down from the previous run.
+        self.send_update(JobUpdate::AngelStatus(AngelStatus::Running)).await?;
+        if let Some(snapshot) = snapshot.as_mut() {
+            self.restore_active_states(snapshot);
+            snapshot.data.active_states = self.data.active_states.clone();
+            snapshot.data.next_active_states = None;
+        }
+        self.send_update(JobUpdate::ActiveStates {
+            current: self.data.active_states.clone(),
+            next: None,
+        })
+        .await?;
+        let Some(mut snapshot) = snapshot else {
+            return self.reset().await;
+        };
+        snapshot.data.port_disconnected = None;
+        snapshot.data.angel_status = AngelStatus::Running;
+        snapshot.data.takeover = None;
+        let interrupted = snapshot.data.job_started.is_some()
+            && snapshot.data.job_ended.is_none()
+            && !snapshot.data.get_status(&self.severity_policy).is_idle();
+
+        if interrupted && self.resume_jobs && self.state_machine.state(&snapshot.current_state).is_ok() {
+            info!("Resuming job in state {:?}.", snapshot.current_state);
+            if let Some(tracing_log_file) = snapshot.tracing_log_file.as_ref() {
+                self.tracing_target.append_file(tracing_log_file)?;
+            }
+            if let Some(raw_log_file) = snapshot.raw_log_file.as_ref() {
+                self.rawlog_target.append_file(raw_log_file)?;
+            }
+            if let Some(events_log_file) = snapshot.events_log_file.as_ref() {
+                self.events_target.append_file(events_log_file)?;
+            }
+            if let Some(cast_file) = snapshot.cast_file.as_ref() {
+                self.cast_target.append_file(cast_file)?;
+            }
+            self.current_state = snapshot.current_state;
+            self.tracing_log_file = snapshot.tracing_log_file;
+            self.raw_log_file = snapshot.raw_log_file;
+            self.events_log_file = snapshot.events_log_file;
+            self.cast_file = snapshot.cast_file;
+            return self.send_update(JobUpdate::JobFullData(snapshot.data)).await;
+        }
+
+        if snapshot.data.job_started.is_some() {
+            info!("Republishing the previous job.");
+            // A job that never ended was never archived, let the reset below do it.
+            if snapshot.data.job_ended.is_none() {
+                self.tracing_log_file = snapshot.tracing_log_file;
+                self.raw_log_file = snapshot.raw_log_file;
+                self.events_log_file = snapshot.events_log_file;
+                self.cast_file = snapshot.cast_file;
+            }
+            self.send_update(JobUpdate::JobFullData(snapshot.data)).await?;
+            if interrupted {
+                warn!("The previous job was interrupted, ending it.");
+                self.add_information(DeviceInformation::Aborted).await?;
+                self.send_update(JobUpdate::JobEnd(Utc::now())).await?;
+            }
+        }
+        self.reset().await
+    }
+
+    /// Keep the state files switched to from heaven, unless the config has changed since.
+    ///
+    /// A pending switch is applied as well, the restart already interrupted the job.
+    fn restore_active_states(&mut self, snapshot: &JobSnapshot) {
+        if snapshot.configured_active_states != self.configured_active_states {
+            return;
+        }
+        let data = &snapshot.data;
+        let active_states = data.next_active_states.as_ref().unwrap_or(&data.active_states);
+        if active_states.is_empty() || *active_states == self.data.active_states {
+            return;
+        }
+        match build_state_machine(active_states) {
+            Ok(state_machine) => {
+                info!("Restoring state files {active_states:?}.");
+                self.state_machine = state_machine;
+                self.data.active_states = active_states.clone();
+            }
+            Err(e) => warn!("Unable to restore state files {active_states:?}: {e:?}"),
+        }
+    }
+
+    /// Build and publish the erasure certificate, a copy is kept next to the logs.
+    async fn issue_certificate(&mut self, job_ended: chrono::DateTime<Utc>) -> color_eyre::Result<()> {
+        let transcript_sha256 = if let Some(raw_log_file) = self.raw_log_file.as_ref() {
+            Some(hash_file(raw_log_file).await.context("hash raw serial transcript")?)
+        } else {
+            None
+        };
+        let mut data = self.data.clone();
+        data.job_ended = Some(job_ended);
+        let certificate = sign_certificate(
+            ErasureCertificate::from_job(&data, transcript_sha256),
+            self.certificate_key.as_ref(),
+        )?;
+
+        if let Some(raw_log_file) = self.raw_log_file.as_ref() {
+            let certificate_file = certificate_file(raw_log_file);
+            tokio::fs::write(&certificate_file, serde_json::to_vec_pretty(&certificate)?)
+                .await
+                .context("write erasure certificate")?;
+            info!("Wrote erasure certificate to {certificate_file:?}.");
+        }
+
+        self.send_update(JobUpdate::JobCertificate(Box::new(certificate))).await
+    }
+
+    fn has_unarchived_logs(&self) -> bool {
+        self.tracing_log_file.is_some()
+            || self.raw_log_file.is_some()
+            || self.events_log_file.is_some()
+            || self.cast_file.is_some()
+    }
+
+    /// Close the logs of a finished job, so that the transcript stays as certified, and hand them to retention.
+    async fn archive_logs(&mut self, job_ended: DateTime<Utc>) -> color_eyre::Result<()> {
+        let Some(log_dir) = self.log_dir.clone() else {
+            return Ok(());
+        };
+        self.tracing_target.close()?;
+        self.rawlog_target.close()?;
+        self.events_target.close()?;
+        self.cast_target.close()?;
+        let certificate_file = self.raw_log_file.as_deref().map(certificate_file);
+        let mut files = Vec::new();
+        for file in [
+            self.tracing_log_file.take(),
+            self.raw_log_file.take(),
+            self.events_log_file.take(),
+            self.cast_file.take(),
+            certificate_file,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if tokio::fs::try_exists(&file).await? {
+                files.push(file);
+            }
+        }
+        let serial_number = self.data.info_items.iter().find_map(|i| match i {
+            DeviceInformation::SerialNumber(s) => Some(s.clone()),
+            _ => None,
+        });
+        let entry = LogIndexEntry {
+            port: self.mqtt.id().to_string(),
+            serial_number,
+            job_started: self.data.job_started,
+            job_ended,
+            files,
+        };
+        archive_job(&log_dir, &self.log_retention, entry).await
+    }
+
     async fn transition(
         &mut self,
         t: &StateMachineTransition,
@@ -126,6 +481,11 @@ impl ActiveJob {
         info!("State transition: {:?} -> {:?}", old_state, t.target);
         self.send_update(JobUpdate::JobStageTransition(Utc::now(), t.target.clone()))
             .await?;
+        self.record_event(JobEvent::Transition {
+            from: &old_state,
+            trigger: &t.trigger,
+            matched: m,
+        });
         for action in &t.actions {
             action.perform(self, p, d, m).await?;
         }
@@ -141,58 +501,436 @@ impl ActiveJob {
         Ok(())
     }
 
-    pub async fn step(&mut self, p: &mut SwitchExpect) -> color_eyre::Result<()> {
+    /// Wait for one of the transitions out of the current state to trigger.
+    ///
+    /// Nothing about the job changes until a trigger has matched, and output that did not match yet stays
+    /// buffered in `p`, so this can be cancelled at any point. `None` means there is nothing to take, as
+    /// while the port is taken over.
+    pub async fn wait_for_trigger(
+        &self,
+        p: &mut SwitchExpect,
+    ) -> color_eyre::Result<Option<Triggered>> {
+        if self.data.takeover.is_some() {
+            // The operator is in control, only keep the output flowing to heaven.
+            p.expect(&ReadUntil::Regex(Regex::new("(?s).+")?))
+                .await
+                .context("failed to read from serial port")?;
+            return Ok(None);
+        }
+
         let s = self.state_machine.state(&self.current_state)?;
-        let transitions = &s.transitions;
+        let transitions = s.transitions;
 
         if let Some(t) = transitions
             .iter()
             .find(|t| t.trigger == StateMachineTrigger::Immediate)
         {
-            self.transition(t, p, "", "")
-                .await
-                .context("process immediate transition")?;
-        } else {
-            let u = ReadUntil::Any(
-                transitions
-                    .iter()
-                    .map(|t| t.trigger.to_needle().map(|v| v.unwrap()))
-                    .collect::<color_eyre::Result<Vec<_>>>()?,
-            );
-
-            // Try to handle a result from the switches.
-            debug!("Waiting for needle {u:?}...");
-            let (d, m) = p
-                .expect(&u)
-                .await
-                .context("failed to read from serial port")?;
-            't_test: for t in transitions.iter() {
-                if t.trigger.matches_result(&m)? {
-                    self.transition(&t, p, &d, &m)
-                        .await
-                        .context("process serial transition")?;
-                    break 't_test;
-                }
+            return Ok(Some(Triggered {
+                transition: t.clone(),
+                data: String::new(),
+                matched: String::new(),
+            }));
+        }
+
+        let u = ReadUntil::Any(
+            transitions
+                .iter()
+                .map(|t| t.trigger.to_needle().map(|v| v.unwrap()))
+                .collect::<color_eyre::Result<Vec<_>>>()?,
+        );
+
+        // Try to handle a result from the switches.
+        debug!("Waiting for needle {u:?}...");
+        let (data, matched) = p
+            .expect(&u)
+            .await
+            .context("failed to read from serial port")?;
+        for t in transitions.into_iter() {
+            if t.trigger.matches_result(&matched)? {
+                return Ok(Some(Triggered {
+                    transition: t,
+                    data,
+                    matched,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Take a triggered transition and run all of its actions.
+    ///
+    /// The actions drive the device, stopping halfway leaves it in a state the job does not know about.
+    /// Never race this against anything.
+    pub async fn take_transition(
+        &mut self,
+        p: &mut SwitchExpect,
+        triggered: Triggered,
+    ) -> color_eyre::Result<()> {
+        let Triggered {
+            transition,
+            data,
+            matched,
+        } = triggered;
+        self.transition(&transition, p, &data, &matched)
+            .await
+            .context("process transition")
+    }
+
+    pub async fn port_disconnected(&mut self, at: DateTime<Utc>) -> color_eyre::Result<()> {
+        warn!("Serial port disconnected, holding the job until it is back.");
+        self.send_update(JobUpdate::PortDisconnected(at)).await
+    }
+
+    /// Resume after a disconnect, a gap in the middle of a job is noted on the device.
+    pub async fn port_reconnected(&mut self, at: DateTime<Utc>) -> color_eyre::Result<()> {
+        let gap = self
+            .data
+            .port_disconnected
+            .map(|since| (at - since).num_seconds().max(0) as u64)
+            .unwrap_or_default();
+        info!("Serial port reconnected after {gap}s.");
+        self.send_update(JobUpdate::PortReconnected(at)).await?;
+        if !self.data.get_status(&self.severity_policy).is_idle() {
+            let previous = self.data.info_items.iter().find_map(|i| match i {
+                DeviceInformation::SerialConnectionLost(s) => Some(*s),
+                _ => None,
+            });
+            self.add_information(DeviceInformation::SerialConnectionLost(
+                previous.unwrap_or_default() + gap,
+            ))
+            .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn take_over(&mut self, session: String) -> color_eyre::Result<()> {
+        if self.data.takeover.is_some() {
+            return Ok(());
+        }
+        info!("Port taken over by an operator in state {:?}.", self.current_state);
+        self.send_update(JobUpdate::PortTakenOver(Takeover {
+            since: Utc::now(),
+            session,
+            states: self.state_machine.states(),
+        }))
+        .await
+    }
+
+    /// Give the port back to the state machine, entering `state` without running any transition actions.
+    pub async fn release(&mut self, state: Option<String>) -> color_eyre::Result<()> {
+        if self.data.takeover.is_none() {
+            return Ok(());
+        }
+        if let Some(state) = state.filter(|s| *s != self.current_state) {
+            if self.state_machine.get_state(&state).is_none() {
+                warn!("Unknown state {state:?}, staying in {:?}.", self.current_state);
+            } else {
+                info!("Operator moved the job: {:?} -> {:?}", self.current_state, state);
+                self.current_state = state;
+                self.send_update(JobUpdate::JobStageTransition(Utc::now(), self.current_state.clone()))
+                    .await?;
             }
         }
+        info!("Port released by the operator.");
+        self.send_update(JobUpdate::PortReleased(Utc::now())).await
+    }
 
+    /// Record a manual action on the state machine, moving the job if it is a goto.
+    pub async fn operator_action(&mut self, action: OperatorAction) -> color_eyre::Result<()> {
+        if let OperatorAction::GotoState(state) = &action
+            && self.state_machine.get_state(state).is_none()
+        {
+            warn!("Ignoring jump to unknown state {state:?}.");
+            return Ok(());
+        }
+        info!("Operator action: {action}");
+        self.send_update(JobUpdate::OperatorAction(Utc::now(), action.clone()))
+            .await?;
+        if let OperatorAction::GotoState(state) = action {
+            info!("State transition: {:?} -> {:?}", self.current_state, state);
+            self.current_state = state;
+            self.send_update(JobUpdate::JobStageTransition(Utc::now(), self.current_state.clone()))
+                .await?;
+        }
         Ok(())
     }
 
-    pub async fn flag_restart(&mut self) -> color_eyre::Result<()> {
-        if self.data.get_status().is_idle() {
-            panic!("Crash requested!");
+    /// Switch to another set of state files, right away when idle, otherwise once the job is done.
+    pub async fn set_active_states(&mut self, active_states: Vec<String>) -> color_eyre::Result<()> {
+        let state_machine = match build_state_machine(&active_states) {
+            Ok(state_machine) => state_machine,
+            Err(e) => {
+                warn!("Ignoring switch to state files {active_states:?}: {e:?}");
+                return self
+                    .send_update(JobUpdate::ActiveStatesRejected(Box::new(RejectedActiveStates {
+                        states: active_states,
+                        reason: format!("{e:#}"),
+                    })))
+                    .await;
+            }
+        };
+        if self.is_idle() && state_machine.get_state(&self.current_state).is_some() {
+            info!("Switched to state files {active_states:?}.");
+            self.state_machine = state_machine;
+            self.next_state_machine = None;
+            self.send_update(JobUpdate::ActiveStates {
+                current: active_states,
+                next: None,
+            })
+            .await?;
+        } else {
+            info!("Switching to state files {active_states:?} once the job is done.");
+            self.next_state_machine = Some(state_machine);
+            self.send_update(JobUpdate::ActiveStates {
+                current: self.data.active_states.clone(),
+                next: Some(active_states),
+            })
+            .await?;
         }
-        self.shutdown_requested = true;
+        // The switch has to survive a restart, even if no transition follows.
+        self.checkpoint().await;
         Ok(())
     }
 
+    /// When the watchdog ends the job, if it is running and has a limit.
+    pub fn watchdog_deadline(&self, watchdog: &WatchdogConfig) -> Option<DateTime<Utc>> {
+        // Idle, paused, taken over or disconnected ports are not stuck.
+        if !matches!(
+            self.data.get_status(&self.severity_policy),
+            JobStatus::Busy | JobStatus::RunningLong
+        ) {
+            return None;
+        }
+        let job_deadline = watchdog
+            .job_timeout_secs
+            .zip(self.data.job_started)
+            .map(|(secs, started)| started + TimeDelta::seconds(secs as i64));
+        let state_deadline = watchdog
+            .state_timeout(&self.current_state)
+            .zip(self.data.get_last_updated())
+            .map(|(secs, updated)| updated + TimeDelta::seconds(secs as i64));
+        job_deadline.into_iter().chain(state_deadline).min()
+    }
+
+    /// End a stuck job with an error, entering the timeout state without running any transition actions.
+    pub async fn watchdog_expired(&mut self, watchdog: &WatchdogConfig) -> color_eyre::Result<()> {
+        warn!("Watchdog expired in state {:?}! Ending job...", self.current_state);
+        self.add_information(DeviceInformation::JobTimedOut(self.current_state.clone()))
+            .await?;
+        let state = if self.state_machine.get_state(&watchdog.timeout_state).is_some() {
+            watchdog.timeout_state.clone()
+        } else {
+            warn!("Unknown timeout state {:?}, using EndJob.", watchdog.timeout_state);
+            "EndJob".to_string()
+        };
+        info!("State transition: {:?} -> {:?}", self.current_state, state);
+        self.current_state = state;
+        self.send_update(JobUpdate::JobStageTransition(Utc::now(), self.current_state.clone()))
+            .await
+    }
+
+    /// Is there no device being worked on right now?
+    pub fn is_idle(&self) -> bool {
+        self.data.get_status(&self.severity_policy).is_idle()
+    }
+
+    pub async fn set_angel_status(&mut self, status: AngelStatus) -> color_eyre::Result<()> {
+        if self.data.angel_status != status {
+            self.send_update(JobUpdate::AngelStatus(status)).await?;
+        }
+        Ok(())
+    }
+
+    /// Checkpoint the job and flush its logs, before the angel exits.
+    pub async fn shutdown(&mut self) -> color_eyre::Result<()> {
+        self.set_angel_status(AngelStatus::GoingDown).await?;
+        self.save_snapshot().await?;
+        self.tracing_target.close()?;
+        self.rawlog_target.close()?;
+        self.events_target.close()?;
+        self.cast_target.close()?;
+        Ok(())
+    }
+
+    fn record_event(&self, event: JobEvent) {
+        write_event(
+            &self.events_target,
+            &JobEventRecord {
+                time: Utc::now(),
+                state: &self.current_state,
+                raw_log_offset: self.rawlog_target.written_bytes(),
+                event,
+            },
+        );
+    }
+
     async fn send_update(&mut self, update: JobUpdate) -> color_eyre::Result<()> {
+        let transition = matches!(
+            update,
+            JobUpdate::JobStageTransition(..) | JobUpdate::JobStart(_) | JobUpdate::JobEnd(_)
+        );
+        if let JobUpdate::JobStageTransition(_, state) = &update {
+            self.cast_target.marker(state);
+        }
         self.data.update(update.clone());
         self.mqtt.send_update(update).await?;
-        if self.shutdown_requested && self.data.get_status().is_idle() {
-            panic!("Crash requested!");
+        self.mqtt.send_job_data(&self.data).await?;
+        // A job resumes in a state, so a snapshot only has to keep up with the job moving between states.
+        if transition {
+            self.checkpoint().await;
         }
         Ok(())
     }
+
+    async fn checkpoint(&self) {
+        if let Err(e) = self.save_snapshot().await {
+            warn!("Failed to save job snapshot: {e:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cthulhu_common::metrics::Metrics;
+
+    fn states(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn job(active_states: &[&str]) -> ActiveJob {
+        let active_states = states(active_states);
+        ActiveJob::create(
+            MQTTSender::empty("S1".to_string(), Metrics::new(SeverityPolicy::default())),
+            None,
+            TracingTarget::default(),
+            TracingTarget::default(),
+            CastTarget::default(),
+            active_states.clone(),
+            build_state_machine(&active_states).unwrap(),
+            BTreeMap::new(),
+            None,
+            SeverityPolicy::default(),
+            false,
+            LogRetentionConfig::default(),
+            PortControl::detached(),
+        )
+    }
+
+    #[tokio::test]
+    async fn switch_active_states() {
+        let mut job = job(&["wipe"]);
+        job.send_update(JobUpdate::JobStageTransition(Utc::now(), "Init".to_string())).await.unwrap();
+
+        // Idle, the switch happens right away.
+        job.set_active_states(states(&["wipe", "provision"])).await.unwrap();
+        assert_eq!(job.data.active_states, states(&["wipe", "provision"]));
+        assert!(job.next_state_machine.is_none());
+
+        job.set_active_states(states(&["nope"])).await.unwrap();
+        let rejected = job.data.rejected_active_states.clone().unwrap();
+        assert_eq!(rejected.states, states(&["nope"]));
+        assert_eq!(job.data.active_states, states(&["wipe", "provision"]));
+
+        // Busy, the switch waits for the job to be done.
+        job.send_update(JobUpdate::JobStageTransition(Utc::now(), "Wipe".to_string())).await.unwrap();
+        job.set_active_states(states(&["wipe"])).await.unwrap();
+        assert_eq!(job.data.active_states, states(&["wipe", "provision"]));
+        assert_eq!(job.data.next_active_states, Some(states(&["wipe"])));
+        assert!(job.data.rejected_active_states.is_none());
+        assert!(job.next_state_machine.is_some());
+
+        job.reset().await.unwrap();
+        assert_eq!(job.data.active_states, states(&["wipe"]));
+        assert!(job.data.next_active_states.is_none());
+    }
+
+    #[test]
+    fn restore_active_states() {
+        let snapshot = |configured: &[&str], active: &[&str], next: Option<&[&str]>| JobSnapshot {
+            data: JobData {
+                active_states: states(active),
+                next_active_states: next.map(states),
+                ..JobData::with_label("S1")
+            },
+            current_state: "Init".to_string(),
+            tracing_log_file: None,
+            raw_log_file: None,
+            events_log_file: None,
+            cast_file: None,
+            configured_active_states: states(configured),
+        };
+
+        let mut restored = job(&["wipe"]);
+        restored.restore_active_states(&snapshot(&["wipe"], &["wipe", "provision"], None));
+        assert_eq!(restored.data.active_states, states(&["wipe", "provision"]));
+
+        // A pending switch is applied, the restart interrupted the job anyway.
+        let mut pending = job(&["wipe"]);
+        pending.restore_active_states(&snapshot(&["wipe"], &["wipe"], Some(&["recover"])));
+        assert_eq!(pending.data.active_states, states(&["recover"]));
+
+        // The config changed since the snapshot, it wins.
+        let mut changed = job(&["wipe"]);
+        changed.restore_active_states(&snapshot(&["recover"], &["wipe", "provision"], None));
+        assert_eq!(changed.data.active_states, states(&["wipe"]));
+    }
+
+    async fn move_to(job: &mut ActiveJob, at: DateTime<Utc>, state: &str) {
+        job.current_state = state.to_string();
+        job.send_update(JobUpdate::JobStageTransition(at, state.to_string())).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn watchdog_deadline() {
+        let mut job = job(&["wipe"]);
+        let started = Utc::now() - TimeDelta::seconds(60);
+        job.send_update(JobUpdate::JobStart(started)).await.unwrap();
+        move_to(&mut job, started, "Init").await;
+        let watchdog = WatchdogConfig {
+            job_timeout_secs: Some(3600),
+            state_timeout_secs: Some(600),
+            ..WatchdogConfig::default()
+        };
+        // Waiting for a device is not being stuck.
+        assert_eq!(job.watchdog_deadline(&watchdog), None);
+
+        let entered = started + TimeDelta::seconds(30);
+        move_to(&mut job, entered, "JunosLogin").await;
+        assert_eq!(job.watchdog_deadline(&watchdog), Some(entered + TimeDelta::seconds(600)));
+        assert_eq!(job.watchdog_deadline(&WatchdogConfig::default()), None);
+
+        // A slow state gets more time, but never more than the whole job.
+        let mut slow = watchdog.clone();
+        slow.state_timeouts.insert("JunosLogin".to_string(), 7200);
+        assert_eq!(slow.state_timeout("JunosLogin"), Some(7200));
+        assert_eq!(slow.state_timeout("JunosHappyCli"), Some(600));
+        assert_eq!(job.watchdog_deadline(&slow), Some(started + TimeDelta::seconds(3600)));
+
+        slow.job_timeout_secs = None;
+        assert_eq!(job.watchdog_deadline(&slow), Some(entered + TimeDelta::seconds(7200)));
+    }
+
+    #[tokio::test]
+    async fn watchdog_expired() {
+        let mut job = job(&["wipe"]);
+        job.send_update(JobUpdate::JobStart(Utc::now())).await.unwrap();
+        move_to(&mut job, Utc::now(), "JunosHappyCli").await;
+        let watchdog = WatchdogConfig {
+            timeout_state: "JunosLogin".to_string(),
+            ..WatchdogConfig::default()
+        };
+        job.watchdog_expired(&watchdog).await.unwrap();
+        assert_eq!(job.current_state, "JunosLogin");
+        assert!(job.data.info_items.contains(&DeviceInformation::JobTimedOut("JunosHappyCli".to_string())));
+
+        // A timeout state that does not exist must not leave the job hanging.
+        let watchdog = WatchdogConfig {
+            timeout_state: "Nope".to_string(),
+            ..WatchdogConfig::default()
+        };
+        job.watchdog_expired(&watchdog).await.unwrap();
+        assert_eq!(job.current_state, "EndJob");
+        assert_eq!(job.data.get_current_stage(), Some("EndJob"));
+    }
 }
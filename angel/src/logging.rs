@@ -1,18 +1,29 @@
+use crate::mqtt::MQTTSender;
+use chrono::Utc;
+use cthulhu_common::asciicast::{self, CastEncoder};
 use cthulhu_config::angel::AngelConfig;
 use pin_project::pin_project;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
 use std::fs::File;
 use std::io::{Error, Write};
 use std::path::Path;
 use std::pin::Pin;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use std::task::{Context, Poll, ready};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::mpsc::UnboundedSender;
 use tokio_util::io::InspectReader;
+use tracing::field::{Field, Visit};
 use tracing::level_filters::LevelFilter;
-use tracing::{Level, info};
+use tracing::span::{Attributes, Id};
+use tracing::{Level, Subscriber, info, warn};
+use tracing_subscriber::filter::dynamic_filter_fn;
 use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::{Layer, Registry};
 
 pub trait SerialIO: AsyncRead + AsyncWrite + Unpin + Send + Sync {}
@@ -75,12 +86,18 @@ impl<IO: SerialIO> AsyncWrite for SerialLogger<IO> {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct TracingTarget {
     target: Arc<Mutex<Option<File>>>,
 }
 
 impl TracingTarget {
+    /// Size of the open file, every write goes straight to it.
+    pub fn written_bytes(&self) -> Option<u64> {
+        let l = self.target.lock().unwrap();
+        l.as_ref().and_then(|f| f.metadata().ok()).map(|m| m.len())
+    }
+
     pub fn open_file<P: AsRef<Path>>(&self, path: P) -> color_eyre::Result<()> {
         if let Some(p) = path.as_ref().parent() {
             std::fs::create_dir_all(p)?;
@@ -90,6 +107,24 @@ impl TracingTarget {
         *l = Some(f);
         Ok(())
     }
+
+    /// Flush everything to disk and stop logging to the file.
+    pub fn close(&self) -> color_eyre::Result<()> {
+        let mut l = self.target.lock().unwrap();
+        if let Some(mut f) = l.take() {
+            f.flush()?;
+            f.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Like [`TracingTarget::open_file`], but keeps what is already in the file.
+    pub fn append_file<P: AsRef<Path>>(&self, path: P) -> color_eyre::Result<()> {
+        let f = File::options().create(true).append(true).open(path)?;
+        let mut l = self.target.lock().unwrap();
+        *l = Some(f);
+        Ok(())
+    }
 }
 
 pub struct TracingTargetWriter {
@@ -126,21 +161,80 @@ impl<'a> MakeWriter<'a> for TracingTarget {
     }
 }
 
-pub async fn setup_tracing(config: &AngelConfig) -> color_eyre::Result<TracingTarget> {
+/// Port a span belongs to, taken from its `port` field.
+struct PortLabel(String);
+
+struct PortLabelVisitor(Option<String>);
+
+impl Visit for PortLabelVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "port" {
+            self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "port" {
+            self.0 = Some(format!("{value:?}"));
+        }
+    }
+}
+
+/// Tags spans with their port, so that the file layers can pick out their own events.
+struct PortLabelLayer;
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for PortLabelLayer {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: tracing_subscriber::layer::Context<'_, S>) {
+        let mut visitor = PortLabelVisitor(None);
+        attrs.record(&mut visitor);
+        if let Some(label) = visitor.0
+            && let Some(span) = ctx.span(id)
+        {
+            span.extensions_mut().insert(PortLabel(label));
+        }
+    }
+}
+
+/// Sets up logging to stdout, and to a job log file for every port.
+///
+/// Events end up in the file of the port whose `port` span they were emitted in.
+pub async fn setup_tracing(
+    config: &AngelConfig,
+    port_ids: &[String],
+) -> color_eyre::Result<BTreeMap<String, TracingTarget>> {
     let max_log_level =
         Level::from_str(&(config.log_level.as_ref().unwrap_or(&"info".to_string())))?;
-    let target = TracingTarget {
-        target: Arc::new(Mutex::new(None)),
-    };
+    let mut targets = BTreeMap::new();
+    let mut filesubs = Vec::new();
+    for id in port_ids {
+        let target = TracingTarget::default();
+        let label = id.clone();
+        let filesub = tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_writer(target.clone())
+            .with_filter(dynamic_filter_fn(move |meta, cx| {
+                if meta.is_span() {
+                    return true;
+                }
+                *meta.level() <= max_log_level
+                    && cx.lookup_current().is_some_and(|span| {
+                        span.scope().any(|s| {
+                            s.extensions().get::<PortLabel>().is_some_and(|p| p.0 == label)
+                        })
+                    })
+            }))
+            .boxed();
+        filesubs.push(filesub);
+        targets.insert(id.clone(), target);
+    }
     let stdsub =
         tracing_subscriber::fmt::layer().with_filter(LevelFilter::from_level(max_log_level));
-    let filesub = tracing_subscriber::fmt::layer()
-        .with_ansi(false)
-        .with_writer(target.clone())
-        .with_filter(LevelFilter::from_level(max_log_level));
-    let subscriber = Registry::default().with(stdsub).with(filesub);
+    let subscriber = Registry::default()
+        .with(filesubs)
+        .with(PortLabelLayer)
+        .with(stdsub);
     tracing::subscriber::set_global_default(subscriber)?;
-    Ok(target)
+    Ok(targets)
 }
 
 pub async fn wrap_raw_serial_log<IO: 'static + AsyncRead + AsyncWrite + Unpin + Send + Sync>(
@@ -149,9 +243,7 @@ pub async fn wrap_raw_serial_log<IO: 'static + AsyncRead + AsyncWrite + Unpin +
     impl 'static + AsyncRead + AsyncWrite + Unpin + Send + Sync,
     TracingTarget,
 )> {
-    let target = TracingTarget {
-        target: Arc::new(Mutex::new(None)),
-    };
+    let target = TracingTarget::default();
     let io = {
         let target = target.clone();
         InspectReader::new(inp, move |d| {
@@ -162,3 +254,121 @@ pub async fn wrap_raw_serial_log<IO: 'static + AsyncRead + AsyncWrite + Unpin +
     };
     Ok((io, target))
 }
+
+struct CastFile {
+    file: File,
+    /// Heaven gets a copy of every line, so it can replay the job with the same timing.
+    stream: Option<UnboundedSender<Vec<u8>>>,
+    started: Instant,
+    /// Where the timeline of an appended recording left off.
+    offset: f64,
+    encoder: CastEncoder,
+}
+
+impl CastFile {
+    fn write(&mut self, line: &str) {
+        if let Err(e) = self.file.write_all(line.as_bytes()) {
+            warn!("Unable to write to the serial recording: {e}");
+        }
+        if let Some(stream) = self.stream.as_ref() {
+            let _ = stream.send(line.as_bytes().to_vec());
+        }
+    }
+
+    fn now(&self) -> f64 {
+        self.offset + self.started.elapsed().as_secs_f64()
+    }
+}
+
+/// Records the serial output with its timing, as an asciinema cast.
+#[derive(Clone, Default)]
+pub struct CastTarget {
+    target: Arc<Mutex<Option<CastFile>>>,
+    stream: Option<UnboundedSender<Vec<u8>>>,
+}
+
+impl CastTarget {
+    pub fn open_file<P: AsRef<Path>>(&self, path: P, title: &str) -> color_eyre::Result<()> {
+        if let Some(p) = path.as_ref().parent() {
+            std::fs::create_dir_all(p)?;
+        }
+        let mut f = CastFile {
+            file: File::create(path)?,
+            stream: self.stream.clone(),
+            started: Instant::now(),
+            offset: 0.0,
+            encoder: CastEncoder::default(),
+        };
+        f.write(&asciicast::header(Utc::now(), title));
+        let mut l = self.target.lock().unwrap();
+        *l = Some(f);
+        Ok(())
+    }
+
+    /// Continue a recording, the time spent down is left out of the timeline.
+    pub fn append_file<P: AsRef<Path>>(&self, path: P) -> color_eyre::Result<()> {
+        let data = std::fs::read_to_string(path.as_ref())?;
+        let offset = data
+            .lines()
+            .skip(1)
+            .last()
+            .and_then(|l| serde_json::from_str::<(f64, String, String)>(l).ok())
+            .map(|(time, _, _)| time)
+            .unwrap_or_default();
+        let f = File::options().append(true).open(path)?;
+        let mut l = self.target.lock().unwrap();
+        *l = Some(CastFile {
+            file: f,
+            stream: self.stream.clone(),
+            started: Instant::now(),
+            offset,
+            encoder: CastEncoder::default(),
+        });
+        Ok(())
+    }
+
+    pub fn close(&self) -> color_eyre::Result<()> {
+        let mut l = self.target.lock().unwrap();
+        if let Some(mut f) = l.take() {
+            f.file.flush()?;
+            f.file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    pub fn marker(&self, label: &str) {
+        let mut l = self.target.lock().unwrap();
+        if let Some(f) = l.as_mut() {
+            let line = asciicast::marker(f.now(), label);
+            f.write(&line);
+        }
+    }
+
+    fn output(&self, data: &[u8]) {
+        let mut l = self.target.lock().unwrap();
+        if let Some(f) = l.as_mut() {
+            let now = f.now();
+            if let Some(line) = f.encoder.output(now, data) {
+                f.write(&line);
+            }
+        }
+    }
+}
+
+pub async fn wrap_cast_serial_log<IO: 'static + AsyncRead + AsyncWrite + Unpin + Send + Sync>(
+    inp: IO,
+    mqtt_sender: &MQTTSender,
+) -> color_eyre::Result<(
+    impl 'static + AsyncRead + AsyncWrite + Unpin + Send + Sync,
+    CastTarget,
+)> {
+    let target = CastTarget {
+        target: Arc::default(),
+        stream: Some(mqtt_sender.spawn_batcher("cast")),
+    };
+    let io = {
+        let target = target.clone();
+        InspectReader::new(inp, move |d| target.output(d))
+    };
+    Ok((io, target))
+}
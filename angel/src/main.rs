@@ -1,84 +1,376 @@
 use crate::args::Cli;
-use crate::job::ActiveJob;
-use crate::logging::{SerialLogger, setup_tracing, wrap_raw_serial_log};
-use crate::mqtt::{MQTTSender, create_mqtt_sender_from_config, wrap_mqtt_serial_log};
-use crate::ports::port_from_config;
+use crate::certificate::load_signing_key;
+use crate::job::{ActiveJob, build_state_machine};
+use crate::logging::{
+    SerialLogger, TracingTarget, setup_tracing, wrap_cast_serial_log, wrap_raw_serial_log,
+};
+use crate::mqtt::{
+    MQTTSender, PortRoute, create_mqtt_client_from_config, publish_presence, wrap_mqtt_serial_log,
+};
+use crate::ports::reconnect::{PortEvent, ReconnectingSwitchSerialPort};
+use chrono::Utc;
 use clap::Parser;
 use color_eyre::eyre::eyre;
 use cthulhu_angel_sm::AngelJob;
-use cthulhu_angel_sm::builder::StateMachineBuilder;
-use cthulhu_common::status::{JobCommand, JobUpdate};
-use cthulhu_config::angel::AngelConfig;
+use cthulhu_angel_sm::state::StateMachine;
+use cthulhu_common::devinfo::{DeviceInformation, SeverityPolicy};
+use cthulhu_common::metrics::Metrics;
+use cthulhu_common::status::{AngelStatus, JobCommand, JobUpdate, OperatorAction, Presence};
+use cthulhu_config::angel::{AngelConfig, AngelPortEntry, LogRetentionConfig, WatchdogConfig};
+use ed25519_dalek::SigningKey;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 use swexpect::SwitchExpect;
-use tokio::sync::mpsc;
-use tracing::info;
+use tokio::sync::{Mutex, mpsc, watch};
+use tokio::task::JoinSet;
+use tracing::{Instrument, error, info, info_span, warn};
 use cthulhu_config::LoadableConfig;
 
 mod args;
+mod certificate;
+mod events;
 mod job;
 mod logging;
+mod metrics;
 mod mqtt;
 mod ports;
+mod retention;
+
+/// Shutdown requests, for the whole angel or a single port, ordered by urgency.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+enum Shutdown {
+    Running,
+    Drain,
+    RestartWhenIdle,
+    RestartNow,
+}
+
+impl Shutdown {
+    /// The shutdown a command asks for, if any.
+    fn requested_by(command: &JobCommand) -> Option<Self> {
+        match command {
+            JobCommand::Drain => Some(Shutdown::Drain),
+            JobCommand::RestartAngel | JobCommand::RestartWhenIdle => Some(Shutdown::RestartWhenIdle),
+            JobCommand::RestartNow => Some(Shutdown::RestartNow),
+            _ => None,
+        }
+    }
+
+    /// Ask for this shutdown, unless a more urgent one was asked for already.
+    fn request(self, shutdown: &watch::Sender<Shutdown>) {
+        shutdown.send_if_modified(|current| {
+            let escalate = self > *current;
+            if escalate {
+                *current = self;
+            }
+            escalate
+        });
+    }
+}
+
+/// Everything a port needs to (re)start its job.
+#[derive(Clone)]
+struct PortTask {
+    entry: AngelPortEntry,
+    log_dir: Option<PathBuf>,
+    job_config: BTreeMap<String, String>,
+    severity_policy: SeverityPolicy,
+    certificate_key: Option<SigningKey>,
+    resume_jobs: bool,
+    log_retention: LogRetentionConfig,
+    watchdog: WatchdogConfig,
+    active_states: Vec<String>,
+    state_machine: Arc<StateMachine>,
+    mqtt: MQTTSender,
+    tracing_target: TracingTarget,
+    commands: Arc<Mutex<mpsc::Receiver<JobCommand>>>,
+    input: Arc<Mutex<mpsc::Receiver<Vec<u8>>>>,
+    /// Set from `cthulhu/command`, applies to all ports.
+    shutdown: watch::Sender<Shutdown>,
+    /// Set from the command topic of this port, a restart only restarts the port.
+    port_shutdown: watch::Sender<Shutdown>,
+}
 
 #[tokio::main]
 async fn main() -> color_eyre::Result<()> {
     let cli = Cli::parse();
     let config = AngelConfig::from_file(&cli.config).await?;
-    let tracing_target = setup_tracing(&config).await?;
+    let ports = config.get_ports()?;
+    let port_ids: Vec<String> = ports.iter().map(|p| p.id.clone()).collect();
+    let tracing_targets = setup_tracing(&config, &port_ids).await?;
 
     info!("{config:?}");
 
-    let (tx, mut rx) = mpsc::channel(100);
-    let mqtt_sender = if let Some(hconfig) = config.heaven.as_ref() {
-        create_mqtt_sender_from_config(hconfig, tx).await?
+    let certificate_key = if let Some(path) = config.certificate_key.as_ref() {
+        Some(load_signing_key(path).await?)
+    } else {
+        None
+    };
+
+    if let Some(log_dir) = config.log_dir.clone() {
+        tokio::spawn(retention::prune_periodically(log_dir, config.log_retention.clone()));
+    }
+
+    // Ports with the same active states share a state machine.
+    let mut state_machines: BTreeMap<Vec<String>, Arc<StateMachine>> = BTreeMap::new();
+    for port in ports.iter() {
+        let active_states = config.get_active_states(port);
+        if !state_machines.contains_key(active_states) {
+            state_machines.insert(active_states.clone(), build_state_machine(active_states)?);
+        }
+    }
+
+    let mut routes = BTreeMap::new();
+    let mut receivers = BTreeMap::new();
+    for id in port_ids.iter() {
+        let (commands, commands_rx) = mpsc::channel(100);
+        let (input, input_rx) = mpsc::channel(100);
+        routes.insert(id.clone(), PortRoute { commands, input });
+        receivers.insert(id.clone(), (commands_rx, input_rx));
+    }
+    let shutdown = watch::Sender::new(Shutdown::Running);
+    let (mqtt_client, mqtt_eventloop) = if let Some(hconfig) = config.heaven.as_ref() {
+        let (client, eventloop) =
+            create_mqtt_client_from_config(hconfig, routes.clone(), shutdown.clone()).await?;
+        (Some(client), Some(eventloop))
     } else {
-        MQTTSender::empty()
+        (None, None)
     };
 
-    let port = port_from_config(&config.port).await?;
+    let metrics = Metrics::new(config.severity_policy.clone());
+    if let Some(listen_address) = config.metrics_listen_address.clone() {
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve_metrics(listen_address, metrics).await {
+                error!("Serving metrics failed: {e:?}");
+            }
+        });
+    }
+
+    let mut tasks = JoinSet::new();
+    for entry in ports {
+        let id = entry.id.clone();
+        let (commands_rx, input_rx) = receivers.remove(&id).unwrap();
+        let mqtt = if let (Some(client), Some(hconfig)) = (mqtt_client.as_ref(), config.heaven.as_ref()) {
+            MQTTSender::with_client(
+                client.clone(),
+                id.clone(),
+                hconfig.serial_stream.clone(),
+                metrics.clone(),
+            )
+        } else {
+            MQTTSender::empty(id.clone(), metrics.clone())
+        };
+        let task = PortTask {
+            log_dir: config.log_dir.clone(),
+            job_config: config.get_job_config(&entry),
+            severity_policy: config.severity_policy.clone(),
+            certificate_key: certificate_key.clone(),
+            resume_jobs: config.resume_jobs,
+            log_retention: config.log_retention.clone(),
+            watchdog: config.watchdog.clone(),
+            active_states: config.get_active_states(&entry).clone(),
+            state_machine: state_machines[config.get_active_states(&entry)].clone(),
+            mqtt,
+            tracing_target: tracing_targets[&id].clone(),
+            commands: Arc::new(Mutex::new(commands_rx)),
+            input: Arc::new(Mutex::new(input_rx)),
+            shutdown: shutdown.clone(),
+            port_shutdown: watch::Sender::new(Shutdown::Running),
+            entry,
+        };
+        tasks.spawn(supervise_port(task));
+    }
+
+    // Ports only stop for good once a restart was requested.
+    while let Some(r) = tasks.join_next().await {
+        r?;
+    }
+    // Keep the command channels open for as long as the ports run, even without heaven.
+    drop(routes);
+
+    if let (Some(hconfig), Some(client), Some(eventloop)) =
+        (config.heaven.as_ref(), mqtt_client, mqtt_eventloop)
+    {
+        info!("Flushing MQTT...");
+        publish_presence(&client, &hconfig.id, Presence::Offline, &port_ids)?;
+        client.disconnect().await?;
+        if tokio::time::timeout(Duration::from_secs(10), eventloop).await.is_err() {
+            warn!("Timed out flushing MQTT.");
+        }
+    }
+    info!("All ports have stopped, exiting.");
+    Ok(())
+}
+
+/// Keep a port running, a failing or crashing port is restarted without affecting the others.
+async fn supervise_port(task: PortTask) {
+    loop {
+        let id = task.entry.id.clone();
+        let span = info_span!("port", port = %id);
+        let r = tokio::spawn(run_port(task.clone()).instrument(span)).await;
+        match r {
+            Ok(Ok(())) if *task.shutdown.borrow() >= Shutdown::RestartWhenIdle => {
+                info!("Port {id} has shut down.");
+                return;
+            }
+            Ok(Ok(())) => {
+                // Only this port was asked to restart, the others keep going.
+                info!("Port {id} has shut down, restarting...");
+                task.port_shutdown.send_replace(Shutdown::Running);
+                continue;
+            }
+            Ok(Err(e)) => error!("Port {id} has errored, restarting... {e:?}"),
+            Err(e) => error!("Port {id} has crashed, restarting... {e}"),
+        }
+        if *task.shutdown.borrow() >= Shutdown::RestartWhenIdle {
+            return;
+        }
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+async fn run_port(task: PortTask) -> color_eyre::Result<()> {
+    let mut rx = task.commands.lock().await;
+    let mut input = task.input.lock().await;
+
+    let (port, mut port_events, port_control) = ReconnectingSwitchSerialPort::new(&task.entry.port).await?;
     let port = SerialLogger::new(port);
-    let port = wrap_mqtt_serial_log(port, mqtt_sender.clone()).await?;
+    let port = wrap_mqtt_serial_log(port, task.mqtt.clone()).await?;
     let (port, rawlog_target) = wrap_raw_serial_log(port).await?;
+    let (port, cast_target) = wrap_cast_serial_log(port, &task.mqtt).await?;
     let mut p = SwitchExpect::new(port, None);
 
-    let mut smb = StateMachineBuilder::new();
-    smb.load_builtin_state_files()?;
-    for id in config.active_states.iter() {
-        smb.activate_state_file(id)?;
-    }
-    let sm = smb.build()?;
-
     let mut job = ActiveJob::create(
-        mqtt_sender.clone(),
-        config.log_dir.clone(),
-        tracing_target,
+        task.mqtt.clone(),
+        task.log_dir.clone(),
+        task.tracing_target.clone(),
         rawlog_target,
-        sm,
-        config.job_config.clone(),
+        cast_target,
+        task.active_states.clone(),
+        task.state_machine.clone(),
+        task.job_config.clone(),
+        task.certificate_key.clone(),
+        task.severity_policy.clone(),
+        task.resume_jobs,
+        task.log_retention.clone(),
+        port_control,
     );
-    job.reset().await?;
+    job.resume_or_reset().await?;
 
+    let mut shutdown = task.shutdown.subscribe();
+    let mut port_shutdown = task.port_shutdown.subscribe();
+    let mut step_requested = false;
     loop {
+        // A requested shutdown waits for the device on this port to be done, unless it is urgent.
+        let mode = (*shutdown.borrow_and_update()).max(*port_shutdown.borrow_and_update());
+        let parked = mode == Shutdown::RestartNow || (mode != Shutdown::Running && job.is_idle());
+        if parked && mode >= Shutdown::RestartWhenIdle {
+            info!("Shutting down port...");
+            return job.shutdown().await;
+        }
+        job.set_angel_status(if mode == Shutdown::Running {
+            AngelStatus::Running
+        } else {
+            AngelStatus::Draining
+        })
+        .await?;
+        // Taken over ports keep stepping, that is what passes the output on.
+        let run = !parked && (!job.data.paused || step_requested || job.data.takeover.is_some());
+        let watchdog = job.watchdog_deadline(&task.watchdog).filter(|_| !parked);
+        let watchdog_sleep = watchdog
+            .map(|deadline| (deadline - Utc::now()).to_std().unwrap_or_default())
+            .unwrap_or_default();
+
+        // Events that came in during a transition are handled before the next wait, in this order, so a
+        // disconnect or a detected baud rate is on record before the job takes another transition, and a
+        // Pause, Step or GotoState sent meanwhile applies at the boundary rather than one transition late.
         tokio::select! {
+            biased;
+            event = port_events.recv() => {
+                match event {
+                    Some(PortEvent::Disconnected(at)) => job.port_disconnected(at).await?,
+                    Some(PortEvent::Reconnected(at)) => job.port_reconnected(at).await?,
+                    Some(PortEvent::BaudrateDetected(baudrate)) => {
+                        job.add_information(DeviceInformation::Baudrate(baudrate)).await?
+                    },
+                    None => return Err(eyre!("Serial port task has stopped.")),
+                }
+            },
             msg = rx.recv() => {
                 if let Some(cmd) = msg {
                     match cmd {
                         JobCommand::ResetJob => {
                             job.reset().await?;
                         },
-                        JobCommand::RestartAngel => {
-                            job.flag_restart().await?;
+                        // The angel-wide ones never get here, see `create_mqtt_client_from_config`.
+                        JobCommand::RestartAngel | JobCommand::RestartWhenIdle => {
+                            Shutdown::RestartWhenIdle.request(&task.port_shutdown);
+                        },
+                        JobCommand::RestartNow => {
+                            Shutdown::RestartNow.request(&task.port_shutdown);
+                        },
+                        JobCommand::Drain => {
+                            Shutdown::Drain.request(&task.port_shutdown);
+                        },
+                        JobCommand::Takeover { session } => {
+                            job.take_over(session).await?;
+                        },
+                        JobCommand::Release { state } => {
+                            job.release(state).await?;
+                        },
+                        JobCommand::Pause => {
+                            job.operator_action(OperatorAction::Pause).await?;
+                        },
+                        JobCommand::Resume => {
+                            job.operator_action(OperatorAction::Resume).await?;
+                        },
+                        JobCommand::Step => {
+                            if job.data.paused {
+                                job.operator_action(OperatorAction::Step).await?;
+                                step_requested = true;
+                            } else {
+                                warn!("Ignoring step, the job is not paused.");
+                            }
+                        },
+                        JobCommand::GotoState(state) => {
+                            job.operator_action(OperatorAction::GotoState(state)).await?;
+                        },
+                        JobCommand::SetActiveStates(active_states) => {
+                            job.set_active_states(active_states).await?;
                         },
                         JobCommand::GetJobData => {
-                            mqtt_sender.send_update(JobUpdate::JobFullData(job.data.clone())).await?;
+                            task.mqtt.send_update(JobUpdate::JobFullData(job.data.clone())).await?;
                         },
                     }
                 } else {
                     return Err(eyre!("MQTT broken."));
                 }
             },
-            r = job.step(&mut p) => {
-                r?;
+            _ = shutdown.changed() => {},
+            _ = port_shutdown.changed() => {},
+            data = input.recv() => {
+                if let Some(data) = data {
+                    if job.data.takeover.is_some() {
+                        p.send(&String::from_utf8_lossy(&data)).await?;
+                    } else {
+                        warn!("Ignoring input, the port has not been taken over.");
+                    }
+                }
+            },
+            _ = tokio::time::sleep(watchdog_sleep), if watchdog.is_some() => {
+                job.watchdog_expired(&task.watchdog).await?;
+            },
+            // Only the wait for a trigger is raced against the other events, a triggered transition always
+            // runs all of its actions.
+            triggered = job.wait_for_trigger(&mut p), if run => {
+                // A step is one whole transition, output passed on while taken over does not use it up.
+                if let Some(triggered) = triggered? {
+                    job.take_transition(&mut p, triggered).await?;
+                    step_requested = false;
+                }
             },
         }
     }
@@ -0,0 +1,23 @@
+use axum::Router;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use cthulhu_common::metrics::{self, Metrics};
+use tracing::info;
+
+/// Serve the metrics of the ports of this angel, for Prometheus to scrape.
+pub async fn serve_metrics(listen_address: String, metrics: Metrics) -> color_eyre::Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(render))
+        .with_state(metrics);
+
+    info!("Serving metrics on {listen_address}...");
+    let listener = tokio::net::TcpListener::bind(&listen_address).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn render(State(metrics): State<Metrics>) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, metrics::CONTENT_TYPE)], metrics.render())
+}
@@ -1,16 +1,26 @@
-use cthulhu_common::status::{JobCommand, JobUpdate};
-use cthulhu_config::angel::AngelHeavenConfig;
-use rumqttc::{AsyncClient, Event, Incoming, MqttOptions, QoS};
+use crate::Shutdown;
+use cthulhu_common::job::JobData;
+use cthulhu_common::metrics::Metrics;
+use cthulhu_common::mqtt::mqtt_options;
+use cthulhu_common::serial::SerialBatch;
+use cthulhu_common::status::{AngelPresence, JobCommand, JobUpdate, Presence};
+use cthulhu_config::angel::{AngelHeavenConfig, SerialStreamConfig};
+use rumqttc::{AsyncClient, Event, Incoming, LastWill, MqttOptions, Outgoing, QoS};
+use std::collections::BTreeMap;
 use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::sync::mpsc::Sender;
+use tokio::sync::mpsc::{Sender, UnboundedSender};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
 use tokio_util::io::InspectReader;
 use tracing::{debug, error, info, warn};
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct MQTTSender {
     id: String,
     client: Option<AsyncClient>,
+    serial_stream: SerialStreamConfig,
+    metrics: Metrics,
 }
 
 impl MQTTSender {
@@ -18,29 +28,72 @@ impl MQTTSender {
         &self.id
     }
 
-    pub fn empty() -> Self {
+    pub fn empty(id: String, metrics: Metrics) -> Self {
         Self {
-            id: "".to_string(),
+            id,
             client: None,
+            serial_stream: SerialStreamConfig::default(),
+            metrics,
         }
     }
 
-    pub fn with_client(client: AsyncClient, id: String) -> Self {
+    pub fn with_client(
+        client: AsyncClient,
+        id: String,
+        serial_stream: SerialStreamConfig,
+        metrics: Metrics,
+    ) -> Self {
         Self {
             client: Some(client),
             id,
+            serial_stream,
+            metrics,
         }
     }
-    pub async fn send_log_data(&self, data: &[u8]) -> color_eyre::Result<()> {
+    /// Publish a batch of a byte stream, the serial output or the recording, on `cthulhu/<id>/<stream>`.
+    pub async fn send_batch(&self, stream: &str, batch: &SerialBatch) -> color_eyre::Result<()> {
         if let Some(client) = &self.client {
+            let data = batch.encode(self.serial_stream.compress);
             client
-                .publish(format!("cthulhu/{}/serial", self.id), QoS::AtMostOnce, false, data)
+                .publish(format!("cthulhu/{}/{stream}", self.id), QoS::AtMostOnce, false, data)
                 .await?;
         }
         Ok(())
     }
 
+    /// Stream bytes to heaven in batches, returns where to send them.
+    pub fn spawn_batcher(&self, stream: &'static str) -> UnboundedSender<Vec<u8>> {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<Vec<u8>>();
+        let mqtt_sender = self.clone();
+        tokio::spawn(async move {
+            // Reads are often only a few bytes, collect them instead of sending a message for each.
+            let interval = Duration::from_millis(mqtt_sender.serial_stream.batch_interval_ms);
+            let mut seq = 0;
+            while let Some(mut data) = receiver.recv().await {
+                let deadline = tokio::time::sleep(interval);
+                tokio::pin!(deadline);
+                while data.len() < mqtt_sender.serial_stream.batch_size {
+                    tokio::select! {
+                        _ = &mut deadline => break,
+                        msg = receiver.recv() => match msg {
+                            Some(msg) => data.extend(msg),
+                            None => break,
+                        },
+                    }
+                }
+                // A failed batch still uses up its number, heaven shows the gap.
+                let r = mqtt_sender.send_batch(stream, &SerialBatch { seq, data }).await;
+                seq += 1;
+                if let Err(e) = r {
+                    error!("Error logging: {e}")
+                }
+            }
+        });
+        sender
+    }
+
     pub async fn send_update(&self, update: JobUpdate) -> color_eyre::Result<()> {
+        self.metrics.job_update(&self.id, &update);
         let data = serde_json::to_string(&update)?;
         if let Some(client) = &self.client {
             debug!("Sending update: {update:?}");
@@ -52,58 +105,155 @@ impl MQTTSender {
         }
         Ok(())
     }
+
+    /// Keep the latest job data retained, so heaven can rebuild its board without asking every angel.
+    pub async fn send_job_data(&self, data: &JobData) -> color_eyre::Result<()> {
+        if let Some(client) = &self.client {
+            let data = serde_json::to_vec(data)?;
+            client
+                .publish(format!("cthulhu/{}/job", self.id), QoS::AtLeastOnce, true, data)
+                .await?;
+        }
+        Ok(())
+    }
 }
 
 pub async fn wrap_mqtt_serial_log<IO: 'static + AsyncRead + AsyncWrite + Unpin + Send + Sync>(
     inp: IO,
     mqtt_sender: MQTTSender,
 ) -> color_eyre::Result<impl 'static + AsyncRead + AsyncWrite + Unpin + Send + Sync> {
-    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<Vec<u8>>();
-    tokio::spawn(async move {
-        while let Some(msg) = receiver.recv().await {
-            let r = mqtt_sender.send_log_data(&msg).await;
-            if let Err(e) = r {
-                error!("Error logging: {e}")
-            }
-        }
-    });
+    let sender = mqtt_sender.spawn_batcher("serial");
+    let metrics = mqtt_sender.metrics.clone();
+    let id = mqtt_sender.id.clone();
     Ok(InspectReader::new(inp, move |d| {
+        metrics.serial_data(&id, d.len());
         let _ = sender.send(d.to_vec());
     }))
 }
 
 async fn mqtt_options_from_config(config: &AngelHeavenConfig) -> color_eyre::Result<MqttOptions> {
-    let mut mqttoptions = MqttOptions::new(&config.id, &config.host, config.port);
-    mqttoptions.set_keep_alive(Duration::from_secs(5));
-    Ok(mqttoptions)
+    mqtt_options(&config.id, &config.host, config.port, &config.security).await
+}
+
+fn presence_payload(presence: Presence, ports: &[String]) -> color_eyre::Result<Vec<u8>> {
+    Ok(serde_json::to_vec(&AngelPresence {
+        presence,
+        ports: ports.to_vec(),
+    })?)
+}
+
+/// Replace the retained presence of this angel.
+pub fn publish_presence(
+    client: &AsyncClient,
+    id: &str,
+    presence: Presence,
+    ports: &[String],
+) -> color_eyre::Result<()> {
+    client.try_publish(
+        format!("cthulhu/{id}/presence"),
+        QoS::AtLeastOnce,
+        true,
+        presence_payload(presence, ports)?,
+    )?;
+    Ok(())
+}
+
+/// Where the messages for a single port go.
+#[derive(Clone)]
+pub struct PortRoute {
+    pub commands: Sender<JobCommand>,
+    /// Operator keystrokes, only used while the port is taken over.
+    pub input: Sender<Vec<u8>>,
 }
 
-pub async fn create_mqtt_sender_from_config(
+/// Connect to heaven and route the commands for every port to its job.
+///
+/// Drain and restart commands on `cthulhu/command` are for the whole angel and go to `shutdown` instead,
+/// on the command topic of a port they only concern that port.
+///
+/// The returned task ends once a disconnect has gone out, everything published before it has been sent by then.
+pub async fn create_mqtt_client_from_config(
     hconfig: &AngelHeavenConfig,
-    tx: Sender<JobCommand>,
-) -> color_eyre::Result<MQTTSender> {
-    let (mqtt_client, mut mqtt_eventloop) =
-        rumqttc::AsyncClient::new(mqtt_options_from_config(&hconfig).await?, 10);
+    routes: BTreeMap<String, PortRoute>,
+    shutdown: watch::Sender<Shutdown>,
+) -> color_eyre::Result<(AsyncClient, JoinHandle<()>)> {
+    let ports: Vec<String> = routes.keys().cloned().collect();
+    let mut options = mqtt_options_from_config(hconfig).await?;
+    // The broker tells heaven when we vanish without a clean shutdown.
+    options.set_last_will(LastWill::new(
+        format!("cthulhu/{}/presence", hconfig.id),
+        presence_payload(Presence::Lost, &ports)?,
+        QoS::AtLeastOnce,
+        true,
+    ));
+    let (mqtt_client, mut mqtt_eventloop) = rumqttc::AsyncClient::new(options, 10 * routes.len());
 
-    mqtt_client
-        .subscribe(format!("cthulhu/{}/command", hconfig.id), QoS::AtLeastOnce)
-        .await?;
+    for id in routes.keys() {
+        mqtt_client
+            .subscribe(format!("cthulhu/{}/command", id), QoS::AtLeastOnce)
+            .await?;
+        mqtt_client
+            .subscribe(format!("cthulhu/{}/input", id), QoS::AtLeastOnce)
+            .await?;
+    }
     mqtt_client
         .subscribe(format!("cthulhu/command"), QoS::AtLeastOnce)
         .await?;
 
+    let client = mqtt_client.clone();
     let id = hconfig.id.clone();
-    tokio::spawn(async move {
+    let eventloop = tokio::spawn(async move {
         loop {
             let r = mqtt_eventloop.poll().await;
             if let Ok(notification) = r {
                 match notification {
+                    Event::Outgoing(Outgoing::Disconnect) => return,
+                    // A reconnect after a dropped connection has fired the last will, take it back.
+                    Event::Incoming(Incoming::ConnAck(_)) => {
+                        if let Err(e) = publish_presence(&client, &id, Presence::Online, &ports) {
+                            warn!("Unable to announce presence: {e:?}");
+                        }
+                    }
                     Event::Incoming(Incoming::Publish(payload)) => {
-                        if payload.topic == format!("cthulhu/{}/command", id) || payload.topic == "cthulhu/command" {
-                            let command: JobCommand =
-                                serde_json::from_slice(&payload.payload).unwrap();
-                            info!("Received command: {command:?}");
-                            if let Err(e) = tx.send(command).await {
+                        if let Some(route) = payload
+                            .topic
+                            .strip_prefix("cthulhu/")
+                            .and_then(|t| t.strip_suffix("/input"))
+                            .and_then(|id| routes.get(id))
+                        {
+                            if let Err(e) = route.input.try_send(payload.payload.to_vec()) {
+                                warn!("Unable to TX input: {e:?}");
+                            }
+                            continue;
+                        }
+                        let targets: Vec<&Sender<JobCommand>> = if payload.topic == "cthulhu/command" {
+                            routes.values().map(|r| &r.commands).collect()
+                        } else if let Some(id) = payload
+                            .topic
+                            .strip_prefix("cthulhu/")
+                            .and_then(|t| t.strip_suffix("/command"))
+                        {
+                            routes.get(id).map(|r| &r.commands).into_iter().collect()
+                        } else {
+                            continue;
+                        };
+                        let command: JobCommand = match serde_json::from_slice(&payload.payload) {
+                            Ok(c) => c,
+                            Err(e) => {
+                                warn!("Ignoring malformed command on {}: {e}", payload.topic);
+                                continue;
+                            }
+                        };
+                        info!("Received command on {}: {command:?}", payload.topic);
+                        if payload.topic == "cthulhu/command"
+                            && let Some(mode) = Shutdown::requested_by(&command)
+                        {
+                            mode.request(&shutdown);
+                            continue;
+                        }
+                        // Never wait on a port, a stuck port must not hold up the others.
+                        for tx in targets {
+                            if let Err(e) = tx.try_send(command.clone()) {
                                 warn!("Unable to TX command: {e:?}");
                             }
                         }
@@ -114,5 +264,5 @@ pub async fn create_mqtt_sender_from_config(
         }
     });
 
-    Ok(MQTTSender::with_client(mqtt_client, hconfig.id.clone()))
+    Ok((mqtt_client, eventloop))
 }
@@ -0,0 +1,74 @@
+use cthulhu_config::angel::TTYBaudrate;
+
+/// Rates tried in turn, the most common console speeds first.
+const COMMON_BAUDRATES: [u32; 7] = [9600, 115200, 19200, 38400, 57600, 4800, 2400];
+/// Bytes to look at before judging a rate.
+const SAMPLE_SIZE: usize = 256;
+/// More than this share of non-printable bytes means we are listening at the wrong rate.
+const MAX_GARBAGE_RATIO: f64 = 0.2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Undecided,
+    Good,
+    Garbage,
+}
+
+/// Judges the incoming data of a port by how much of it is readable.
+#[derive(Debug, Default)]
+pub struct BaudDetector {
+    total: usize,
+    garbage: usize,
+}
+
+impl BaudDetector {
+    pub fn feed(&mut self, data: &[u8]) -> Verdict {
+        for &b in data {
+            self.total += 1;
+            if !is_printable(b) {
+                self.garbage += 1;
+            }
+        }
+        if self.total < SAMPLE_SIZE {
+            Verdict::Undecided
+        } else if self.garbage as f64 / self.total as f64 > MAX_GARBAGE_RATIO {
+            Verdict::Garbage
+        } else {
+            Verdict::Good
+        }
+    }
+}
+
+fn is_printable(b: u8) -> bool {
+    // Tab, LF, CR, ESC and BS show up in any normal console output.
+    matches!(b, 0x20..=0x7e | b'\t' | b'\n' | b'\r' | 0x1b | 0x08)
+}
+
+/// The rate to try after `current`.
+pub fn next_baudrate(current: TTYBaudrate) -> TTYBaudrate {
+    let next = match COMMON_BAUDRATES.iter().position(|&b| b == current.0) {
+        Some(i) => COMMON_BAUDRATES[(i + 1) % COMMON_BAUDRATES.len()],
+        None => COMMON_BAUDRATES[0],
+    };
+    TTYBaudrate(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect() {
+        let mut detector = BaudDetector::default();
+        assert_eq!(detector.feed(b"Hit [Enter] to boot immediately, or space bar for command prompt.\r\n"), Verdict::Undecided);
+        assert_eq!(detector.feed(&b"Booting [/kernel]...\r\n".repeat(12)), Verdict::Good);
+
+        let mut detector = BaudDetector::default();
+        let garbage: Vec<u8> = (0..SAMPLE_SIZE).map(|i| if i % 3 == 0 { 0xfe } else { b'x' }).collect();
+        assert_eq!(detector.feed(&garbage), Verdict::Garbage);
+
+        assert_eq!(next_baudrate(TTYBaudrate(9600)), TTYBaudrate(115200));
+        assert_eq!(next_baudrate(TTYBaudrate(2400)), TTYBaudrate(9600));
+        assert_eq!(next_baudrate(TTYBaudrate(1200)), TTYBaudrate(9600));
+    }
+}
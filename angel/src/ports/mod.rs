@@ -1,20 +1,44 @@
 use cthulhu_config::angel::AngelPortConfig;
+use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::oneshot;
 
+pub mod autobaud;
 pub mod rawtcp;
+pub mod reconnect;
+pub mod rfc2217;
+pub mod ssh;
 pub mod tty;
 
-pub(crate) trait SwitchSerialPort: AsyncRead + AsyncWrite + Unpin + Send + Sync {}
+/// How long to hold the BREAK, and where to report whether it was sent.
+pub(crate) type BreakRequest = (Duration, oneshot::Sender<std::io::Result<()>>);
+
+pub(crate) trait SwitchSerialPort: AsyncRead + AsyncWrite + Unpin + Send + Sync {
+    /// Hold the line in the BREAK condition for `duration`, for ports that can.
+    ///
+    /// Some ports only know later whether they can, so the outcome goes to `reply`.
+    fn send_break(&mut self, (duration, reply): BreakRequest) {
+        let _ = duration;
+        let _ = reply.send(Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "this port cannot send a BREAK",
+        )));
+    }
+}
 
 pub async fn port_from_config(
     c: &AngelPortConfig,
 ) -> color_eyre::Result<Box<dyn SwitchSerialPort>> {
     match c {
         AngelPortConfig::TTY(config) => Ok(Box::new(
-            tty::TTYSwitchSerialPort::new(&config.path, config.baudrate.0).await?,
+            tty::TTYSwitchSerialPort::new(config).await?,
         )),
         AngelPortConfig::RawTCP(config) => Ok(Box::new(
             rawtcp::RawTCPSwitchSerialPort::new(&config.endpoint).await?,
         )),
+        AngelPortConfig::RFC2217(config) => Ok(Box::new(
+            rfc2217::RFC2217SwitchSerialPort::new(config).await?,
+        )),
+        AngelPortConfig::SSH(config) => Ok(Box::new(ssh::SSHSwitchSerialPort::new(config).await?)),
     }
 }
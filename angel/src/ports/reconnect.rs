@@ -0,0 +1,270 @@
+use crate::ports::autobaud::{BaudDetector, Verdict, next_baudrate};
+use crate::ports::{BreakRequest, SwitchSerialPort, port_from_config};
+use chrono::{DateTime, Utc};
+use cthulhu_config::angel::AngelPortConfig;
+use pin_project::pin_project;
+use std::io::Error;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info, warn};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+pub enum PortEvent {
+    Disconnected(DateTime<Utc>),
+    Reconnected(DateTime<Utc>),
+    BaudrateDetected(u32),
+}
+
+/// Out of band requests for whatever port is connected at the time.
+#[derive(Clone)]
+pub struct PortControl {
+    breaks: mpsc::UnboundedSender<BreakRequest>,
+}
+
+impl PortControl {
+    /// Data written before may still be on its way, so the BREAK is not ordered with it.
+    pub async fn send_break(&self, duration: Duration) -> color_eyre::Result<()> {
+        let (reply, rx) = oneshot::channel();
+        self.breaks
+            .send((duration, reply))
+            .map_err(|_| color_eyre::eyre::eyre!("serial port task has stopped"))?;
+        Ok(rx.await??)
+    }
+
+    /// Not connected to any port, every BREAK fails.
+    #[cfg(test)]
+    pub fn detached() -> Self {
+        Self {
+            breaks: mpsc::unbounded_channel().0,
+        }
+    }
+}
+
+/// Keeps the job's side of the port open while the connection underneath is re-established.
+///
+/// The first connection is made up front so that a wrong config still fails loudly.
+#[pin_project]
+pub struct ReconnectingSwitchSerialPort {
+    #[pin]
+    stream: DuplexStream,
+}
+
+impl ReconnectingSwitchSerialPort {
+    pub async fn new(
+        config: &AngelPortConfig,
+    ) -> color_eyre::Result<(Self, mpsc::UnboundedReceiver<PortEvent>, PortControl)> {
+        let port = port_from_config(config).await?;
+        let (stream, local) = tokio::io::duplex(64 * 1024);
+        let (events, rx) = mpsc::unbounded_channel();
+        let (breaks, breaks_rx) = mpsc::unbounded_channel();
+        tokio::spawn(reconnect_main(config.clone(), port, local, events, breaks_rx));
+        Ok((ReconnectingSwitchSerialPort { stream }, rx, PortControl { breaks }))
+    }
+}
+
+async fn reconnect_main(
+    mut config: AngelPortConfig,
+    mut port: Box<dyn SwitchSerialPort>,
+    mut local: DuplexStream,
+    events: mpsc::UnboundedSender<PortEvent>,
+    mut breaks: mpsc::UnboundedReceiver<BreakRequest>,
+) {
+    let mut detector = config.autobaud().map(|_| BaudDetector::default());
+    // The rate the device was last known to talk at, only a different one is worth reporting.
+    let mut known_baudrate = config.autobaud().map(|b| b.0);
+    loop {
+        match shuffle(&mut port, &mut local, &mut detector, &mut breaks).await {
+            Shuffle::LocalClosed => return,
+            Shuffle::PortLost(e) => {
+                warn!("Lost connection to the serial port: {e}");
+                // Local ttys can only be opened once.
+                drop(port);
+            }
+            Shuffle::BaudrateDetected => {
+                let baudrate = config.autobaud().unwrap_or_default().0;
+                info!("Detected baud rate {baudrate}.");
+                if known_baudrate != Some(baudrate) {
+                    known_baudrate = Some(baudrate);
+                    let _ = events.send(PortEvent::BaudrateDetected(baudrate));
+                }
+                continue;
+            }
+            Shuffle::WrongBaudrate => {
+                let baudrate = next_baudrate(config.autobaud().unwrap_or_default());
+                info!("Data looks like garbage, trying baud rate {}.", baudrate.0);
+                config = config.with_baudrate(baudrate);
+                detector = Some(BaudDetector::default());
+                // Release the port before opening it again.
+                drop(port);
+                match port_from_config(&config).await {
+                    Ok(p) => {
+                        port = p;
+                        continue;
+                    }
+                    Err(e) => warn!("Reopening the serial port failed: {e}"),
+                }
+            }
+        }
+        let _ = events.send(PortEvent::Disconnected(Utc::now()));
+
+        let mut backoff = INITIAL_BACKOFF;
+        port = loop {
+            // The device cannot hear us, so anything written in the meantime is dropped.
+            if discard_for(&mut local, &mut breaks, backoff).await {
+                return;
+            }
+            match port_from_config(&config).await {
+                Ok(port) => break port,
+                Err(e) => {
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    warn!("Reconnecting to the serial port failed, retrying in {backoff:?}: {e}");
+                }
+            }
+        };
+        info!("Reconnected to the serial port.");
+        let _ = events.send(PortEvent::Reconnected(Utc::now()));
+        // Someone may have plugged in another device meanwhile.
+        detector = config.autobaud().map(|_| BaudDetector::default());
+    }
+}
+
+enum Shuffle {
+    LocalClosed,
+    PortLost(String),
+    BaudrateDetected,
+    WrongBaudrate,
+}
+
+async fn shuffle(
+    port: &mut Box<dyn SwitchSerialPort>,
+    local: &mut DuplexStream,
+    detector: &mut Option<BaudDetector>,
+    breaks: &mut mpsc::UnboundedReceiver<BreakRequest>,
+) -> Shuffle {
+    let mut port_buf = [0u8; 4096];
+    let mut local_buf = [0u8; 4096];
+    loop {
+        tokio::select! {
+            n = port.read(&mut port_buf) => match n {
+                Ok(0) => return Shuffle::PortLost("connection closed".to_string()),
+                Ok(n) => {
+                    if local.write_all(&port_buf[..n]).await.is_err() {
+                        return Shuffle::LocalClosed;
+                    }
+                    if let Some(d) = detector.as_mut() {
+                        match d.feed(&port_buf[..n]) {
+                            Verdict::Undecided => {}
+                            Verdict::Good => {
+                                *detector = None;
+                                return Shuffle::BaudrateDetected;
+                            }
+                            Verdict::Garbage => return Shuffle::WrongBaudrate,
+                        }
+                    }
+                },
+                Err(e) => return Shuffle::PortLost(e.to_string()),
+            },
+            n = local.read(&mut local_buf) => match n {
+                Ok(0) | Err(_) => return Shuffle::LocalClosed,
+                Ok(n) => {
+                    if let Err(e) = port.write_all(&local_buf[..n]).await {
+                        return Shuffle::PortLost(e.to_string());
+                    }
+                },
+            },
+            Some(request) = breaks.recv() => port.send_break(request),
+        }
+    }
+}
+
+/// Drain the job's writes for a while, returns true if the job side went away.
+async fn discard_for(
+    local: &mut DuplexStream,
+    breaks: &mut mpsc::UnboundedReceiver<BreakRequest>,
+    duration: Duration,
+) -> bool {
+    let deadline = tokio::time::sleep(duration);
+    tokio::pin!(deadline);
+    let mut buf = [0u8; 4096];
+    loop {
+        tokio::select! {
+            _ = &mut deadline => return false,
+            n = local.read(&mut buf) => if !matches!(n, Ok(n) if n > 0) {
+                return true;
+            },
+            Some((_, reply)) = breaks.recv() => {
+                let _ = reply.send(Err(std::io::Error::new(
+                    std::io::ErrorKind::NotConnected,
+                    "the serial port is disconnected",
+                )));
+            },
+        }
+    }
+}
+
+impl SwitchSerialPort for ReconnectingSwitchSerialPort {}
+
+impl AsyncRead for ReconnectingSwitchSerialPort {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        self.project().stream.as_mut().poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ReconnectingSwitchSerialPort {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        self.project().stream.as_mut().poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.project().stream.as_mut().poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.project().stream.as_mut().poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cthulhu_config::angel::RawTCPConfig;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn reconnects() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = AngelPortConfig::RawTCP(RawTCPConfig {
+            endpoint: listener.local_addr().unwrap().to_string(),
+        });
+        let (connect, accept) = tokio::join!(ReconnectingSwitchSerialPort::new(&config), listener.accept());
+        let (mut port, mut events, _) = connect.unwrap();
+        let (mut server, _) = accept.unwrap();
+
+        server.write_all(b"login:").await.unwrap();
+        let mut buf = [0u8; 6];
+        port.read_exact(&mut buf).await.unwrap();
+        drop(server);
+        assert!(matches!(events.recv().await, Some(PortEvent::Disconnected(_))));
+
+        let (mut server, _) = listener.accept().await.unwrap();
+        assert!(matches!(events.recv().await, Some(PortEvent::Reconnected(_))));
+        server.write_all(b"again").await.unwrap();
+        let mut buf = [0u8; 5];
+        port.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"again");
+    }
+}
@@ -0,0 +1,452 @@
+use crate::ports::{BreakRequest, SwitchSerialPort};
+use cthulhu_config::angel::{RFC2217Config, SerialFlowControl, SerialParity, SerialStopBits};
+use pin_project::pin_project;
+use std::io::Error;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+const OPT_BINARY: u8 = 0;
+const OPT_ECHO: u8 = 1;
+const OPT_SGA: u8 = 3;
+const OPT_COM_PORT: u8 = 44;
+
+const COM_SET_BAUDRATE: u8 = 1;
+const COM_SET_DATASIZE: u8 = 2;
+const COM_SET_PARITY: u8 = 3;
+const COM_SET_STOPSIZE: u8 = 4;
+const COM_SET_CONTROL: u8 = 5;
+
+const CONTROL_BREAK_ON: u8 = 5;
+const CONTROL_BREAK_OFF: u8 = 6;
+
+/// Options we are willing to enable on our side.
+const LOCAL_OPTIONS: [u8; 3] = [OPT_BINARY, OPT_SGA, OPT_COM_PORT];
+/// Options we are willing to let the console server enable.
+const REMOTE_OPTIONS: [u8; 3] = [OPT_BINARY, OPT_SGA, OPT_ECHO];
+/// Options we ask the console server to enable.
+const REQUESTED_REMOTE_OPTIONS: [u8; 2] = [OPT_BINARY, OPT_SGA];
+
+#[pin_project]
+pub struct RFC2217SwitchSerialPort {
+    #[pin]
+    stream: DuplexStream,
+    breaks: mpsc::UnboundedSender<BreakRequest>,
+}
+
+impl RFC2217SwitchSerialPort {
+    pub async fn new(config: &RFC2217Config) -> color_eyre::Result<Self> {
+        let tcp = TcpStream::connect(&config.endpoint).await?;
+        let (stream, telnet) = tokio::io::duplex(64 * 1024);
+        let (breaks, breaks_rx) = mpsc::unbounded_channel();
+        let line_settings = line_settings(config);
+        let endpoint = config.endpoint.clone();
+        tokio::spawn(async move {
+            if let Err(e) = telnet_main(tcp, telnet, line_settings, breaks_rx).await {
+                warn!("Telnet connection to {endpoint} failed: {e}");
+            }
+        });
+        Ok(RFC2217SwitchSerialPort { stream, breaks })
+    }
+}
+
+/// Shuffle data between the console server and the port, doing all the telnet bookkeeping.
+///
+/// Dropping either side ends the task, which closes the other side as well.
+async fn telnet_main(
+    tcp: TcpStream,
+    telnet: DuplexStream,
+    line_settings: Vec<u8>,
+    mut breaks: mpsc::UnboundedReceiver<BreakRequest>,
+) -> color_eyre::Result<()> {
+    let (mut net_rd, mut net_wr) = tcp.into_split();
+    let (mut local_rd, mut local_wr) = tokio::io::split(telnet);
+    let mut decoder = TelnetDecoder {
+        local_requested: LOCAL_OPTIONS.to_vec(),
+        remote_requested: REQUESTED_REMOTE_OPTIONS.to_vec(),
+        ..Default::default()
+    };
+    net_wr.write_all(&decoder.requests()).await?;
+
+    // Subnegotiations are only allowed once the console server agreed to the com port option.
+    let mut line_settings = Some(line_settings);
+    let mut break_off: Option<Instant> = None;
+    let mut net_buf = [0u8; 4096];
+    let mut local_buf = [0u8; 4096];
+    loop {
+        tokio::select! {
+            n = net_rd.read(&mut net_buf) => {
+                let n = n?;
+                if n == 0 {
+                    return Ok(());
+                }
+                let (data, replies) = decoder.decode(&net_buf[..n]);
+                if !replies.is_empty() {
+                    net_wr.write_all(&replies).await?;
+                }
+                if decoder.local_enabled.contains(&OPT_COM_PORT)
+                    && let Some(settings) = line_settings.take()
+                {
+                    net_wr.write_all(&settings).await?;
+                }
+                local_wr.write_all(&data).await?;
+            },
+            n = local_rd.read(&mut local_buf) => {
+                let n = n?;
+                if n == 0 {
+                    return Ok(());
+                }
+                let binary = decoder.local_enabled.contains(&OPT_BINARY);
+                net_wr.write_all(&encode(&local_buf[..n], binary)).await?;
+            },
+            Some((duration, reply)) = breaks.recv(), if break_off.is_none() => {
+                if decoder.local_enabled.contains(&OPT_COM_PORT) {
+                    let mut out = Vec::new();
+                    com_port_command(&mut out, COM_SET_CONTROL, &[CONTROL_BREAK_ON]);
+                    net_wr.write_all(&out).await?;
+                    break_off = Some(Instant::now() + duration);
+                    let _ = reply.send(Ok(()));
+                } else {
+                    let _ = reply.send(Err(std::io::Error::new(
+                        std::io::ErrorKind::Unsupported,
+                        "the console server did not agree to the com port option",
+                    )));
+                }
+            },
+            _ = tokio::time::sleep_until(break_off.unwrap_or_else(Instant::now)), if break_off.is_some() => {
+                let mut out = Vec::new();
+                com_port_command(&mut out, COM_SET_CONTROL, &[CONTROL_BREAK_OFF]);
+                net_wr.write_all(&out).await?;
+                break_off = None;
+            },
+        }
+    }
+}
+
+/// Configure the remote serial line.
+fn line_settings(config: &RFC2217Config) -> Vec<u8> {
+    let mut out = Vec::new();
+    let parity = match config.parity {
+        SerialParity::None => 1,
+        SerialParity::Odd => 2,
+        SerialParity::Even => 3,
+        SerialParity::Mark => 4,
+        SerialParity::Space => 5,
+    };
+    let stop_bits = match config.stop_bits {
+        SerialStopBits::One => 1,
+        SerialStopBits::Two => 2,
+        SerialStopBits::OnePointFive => 3,
+    };
+    let flow_control = match config.flow_control {
+        SerialFlowControl::None => 1,
+        SerialFlowControl::XonXoff => 2,
+        SerialFlowControl::Hardware => 3,
+    };
+    com_port_command(&mut out, COM_SET_BAUDRATE, &config.baudrate.0.to_be_bytes());
+    com_port_command(&mut out, COM_SET_DATASIZE, &[config.data_bits]);
+    com_port_command(&mut out, COM_SET_PARITY, &[parity]);
+    com_port_command(&mut out, COM_SET_STOPSIZE, &[stop_bits]);
+    com_port_command(&mut out, COM_SET_CONTROL, &[flow_control]);
+    out
+}
+
+fn com_port_command(out: &mut Vec<u8>, command: u8, value: &[u8]) {
+    out.extend([IAC, SB, OPT_COM_PORT, command]);
+    out.extend(escape(value));
+    out.extend([IAC, SE]);
+}
+
+fn escape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for &b in data {
+        if b == IAC {
+            out.push(IAC);
+        }
+        out.push(b);
+    }
+    out
+}
+
+/// Escape the data for the console server, outside of BINARY a bare CR has to be sent as CR NUL.
+fn encode(data: &[u8], binary: bool) -> Vec<u8> {
+    let mut out = escape(data);
+    if !binary {
+        let mut i = 0;
+        while i < out.len() {
+            if out[i] == b'\r' && out.get(i + 1) != Some(&b'\n') {
+                out.insert(i + 1, 0);
+            }
+            i += 1;
+        }
+    }
+    out
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+enum DecoderState {
+    #[default]
+    Data,
+    Iac,
+    Negotiate(u8),
+    Sub,
+    SubIac,
+}
+
+/// Strips telnet commands out of the received data and answers option negotiation.
+#[derive(Default)]
+struct TelnetDecoder {
+    state: DecoderState,
+    local_enabled: Vec<u8>,
+    remote_enabled: Vec<u8>,
+    /// Asked for by us and not answered yet, the answer itself needs no reply.
+    local_requested: Vec<u8>,
+    remote_requested: Vec<u8>,
+    sub: Vec<u8>,
+    last_cr: bool,
+}
+
+impl TelnetDecoder {
+    /// The requests for the options we asked for, to send right after connecting.
+    fn requests(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for &opt in self.local_requested.iter() {
+            out.extend([IAC, WILL, opt]);
+        }
+        for &opt in self.remote_requested.iter() {
+            out.extend([IAC, DO, opt]);
+        }
+        out
+    }
+
+    /// Returns the serial data and the replies that have to go back to the console server.
+    fn decode(&mut self, input: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut data = Vec::with_capacity(input.len());
+        let mut replies = Vec::new();
+        for &b in input {
+            self.state = match (self.state, b) {
+                (DecoderState::Data, IAC) => DecoderState::Iac,
+                (DecoderState::Data, _) => {
+                    // Without BINARY a bare CR is sent as CR NUL.
+                    if !(self.last_cr && b == 0 && !self.remote_enabled.contains(&OPT_BINARY)) {
+                        data.push(b);
+                    }
+                    self.last_cr = b == b'\r';
+                    DecoderState::Data
+                }
+                (DecoderState::Iac, IAC) => {
+                    data.push(IAC);
+                    self.last_cr = false;
+                    DecoderState::Data
+                }
+                (DecoderState::Iac, DO | DONT | WILL | WONT) => DecoderState::Negotiate(b),
+                (DecoderState::Iac, SB) => {
+                    self.sub.clear();
+                    DecoderState::Sub
+                }
+                // NOP, data mark, go ahead and friends carry nothing we care about.
+                (DecoderState::Iac, _) => DecoderState::Data,
+                (DecoderState::Negotiate(command), opt) => {
+                    self.negotiate(command, opt, &mut replies);
+                    DecoderState::Data
+                }
+                (DecoderState::Sub, IAC) => DecoderState::SubIac,
+                (DecoderState::Sub, _) => {
+                    self.sub.push(b);
+                    DecoderState::Sub
+                }
+                (DecoderState::SubIac, SE) => {
+                    debug!("Telnet subnegotiation: {:?}", self.sub);
+                    DecoderState::Data
+                }
+                (DecoderState::SubIac, _) => {
+                    self.sub.push(b);
+                    DecoderState::Sub
+                }
+            };
+        }
+        (data, replies)
+    }
+
+    /// Only answer requests that change an option, so that we never end up in a negotiation loop.
+    fn negotiate(&mut self, command: u8, opt: u8, replies: &mut Vec<u8>) {
+        let local_requested = self.local_requested.contains(&opt);
+        let remote_requested = self.remote_requested.contains(&opt);
+        match command {
+            DO | DONT if local_requested => {
+                self.local_requested.retain(|o| *o != opt);
+                if command == DO {
+                    self.local_enabled.push(opt);
+                } else {
+                    warn!("The console server refused telnet option {opt}.");
+                }
+            }
+            WILL | WONT if remote_requested => {
+                self.remote_requested.retain(|o| *o != opt);
+                if command == WILL {
+                    self.remote_enabled.push(opt);
+                } else {
+                    warn!("The console server refused telnet option {opt}.");
+                }
+            }
+            DO if !self.local_enabled.contains(&opt) => {
+                if LOCAL_OPTIONS.contains(&opt) {
+                    self.local_enabled.push(opt);
+                    replies.extend([IAC, WILL, opt]);
+                } else {
+                    replies.extend([IAC, WONT, opt]);
+                }
+            }
+            DONT if self.local_enabled.contains(&opt) => {
+                self.local_enabled.retain(|o| *o != opt);
+                replies.extend([IAC, WONT, opt]);
+            }
+            WILL if !self.remote_enabled.contains(&opt) => {
+                if REMOTE_OPTIONS.contains(&opt) {
+                    self.remote_enabled.push(opt);
+                    replies.extend([IAC, DO, opt]);
+                } else {
+                    replies.extend([IAC, DONT, opt]);
+                }
+            }
+            WONT if self.remote_enabled.contains(&opt) => {
+                self.remote_enabled.retain(|o| *o != opt);
+                replies.extend([IAC, DONT, opt]);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl SwitchSerialPort for RFC2217SwitchSerialPort {
+    fn send_break(&mut self, request: BreakRequest) {
+        if let Err(mpsc::error::SendError((_, reply))) = self.breaks.send(request) {
+            let _ = reply.send(Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "telnet connection closed",
+            )));
+        }
+    }
+}
+
+impl AsyncRead for RFC2217SwitchSerialPort {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        self.project().stream.as_mut().poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for RFC2217SwitchSerialPort {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        self.project().stream.as_mut().poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.project().stream.as_mut().poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.project().stream.as_mut().poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        let mut decoder = TelnetDecoder::default();
+        let (data, replies) = decoder.decode(&[
+            b'a', IAC, WILL, OPT_ECHO, IAC, DO, OPT_COM_PORT, IAC, DO, 24, b'b', IAC, IAC,
+            IAC, SB, OPT_COM_PORT, 101, 0, 0, IAC, IAC, IAC, SE, b'\r', 0, b'c',
+        ]);
+        assert_eq!(data, vec![b'a', b'b', IAC, b'\r', b'c']);
+        assert_eq!(
+            replies,
+            vec![IAC, DO, OPT_ECHO, IAC, WILL, OPT_COM_PORT, IAC, WONT, 24]
+        );
+
+        // Repeated requests for an enabled option are not answered again.
+        let (_, replies) = decoder.decode(&[IAC, WILL, OPT_ECHO]);
+        assert!(replies.is_empty());
+    }
+
+    #[test]
+    fn encode_cr() {
+        assert_eq!(encode(b"a\rb\r\n", false), b"a\r\0b\r\n");
+        assert_eq!(encode(b"a\r", true), b"a\r");
+        assert_eq!(encode(&[IAC, b'\r'], false), vec![IAC, IAC, b'\r', 0]);
+    }
+
+    #[tokio::test]
+    async fn waits_for_com_port_option() -> color_eyre::Result<()> {
+        use std::time::Duration;
+        use tokio::net::TcpListener;
+        use tokio::sync::oneshot;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let config: RFC2217Config = serde_json::from_value(serde_json::json!({
+            "endpoint": listener.local_addr()?.to_string(),
+            "baudrate": 9600,
+        }))?;
+        let (port, accept) = tokio::join!(RFC2217SwitchSerialPort::new(&config), listener.accept());
+        let mut port = port?;
+        let (mut server, _) = accept?;
+
+        let requests = TelnetDecoder {
+            local_requested: LOCAL_OPTIONS.to_vec(),
+            remote_requested: REQUESTED_REMOTE_OPTIONS.to_vec(),
+            ..Default::default()
+        }
+        .requests();
+        let mut buf = vec![0u8; requests.len()];
+        server.read_exact(&mut buf).await?;
+        assert_eq!(buf, requests);
+
+        // Nothing else goes out before the option was agreed to, a BREAK fails instead.
+        let (reply, rx) = oneshot::channel();
+        port.send_break((Duration::from_millis(10), reply));
+        let early = rx.await?.unwrap_err();
+        assert_eq!(early.kind(), std::io::ErrorKind::Unsupported);
+        let mut one = [0u8; 1];
+        let early = tokio::time::timeout(Duration::from_millis(100), server.read(&mut one)).await;
+        assert!(early.is_err());
+
+        server.write_all(&[IAC, DO, OPT_COM_PORT]).await?;
+        let settings = line_settings(&config);
+        let mut buf = vec![0u8; settings.len()];
+        server.read_exact(&mut buf).await?;
+        assert_eq!(buf, settings);
+
+        let (reply, rx) = oneshot::channel();
+        port.send_break((Duration::from_millis(10), reply));
+        rx.await??;
+        let mut expected = Vec::new();
+        com_port_command(&mut expected, COM_SET_CONTROL, &[CONTROL_BREAK_ON]);
+        com_port_command(&mut expected, COM_SET_CONTROL, &[CONTROL_BREAK_OFF]);
+        let mut buf = vec![0u8; expected.len()];
+        server.read_exact(&mut buf).await?;
+        assert_eq!(buf, expected);
+        Ok(())
+    }
+}
@@ -1,12 +1,12 @@
 use crate::ports::SwitchSerialPort;
-use color_eyre::eyre::OptionExt;
+use color_eyre::eyre::{OptionExt, eyre};
+use cthulhu_config::angel::{SerialFlowControl, SerialParity, SerialStopBits, TTYConfig};
 use pin_project::pin_project;
 use std::io::Error;
-use std::path::Path;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
-use tokio_serial::SerialStream;
+use tokio_serial::{DataBits, FlowControl, Parity, SerialStream, StopBits};
 
 #[pin_project]
 pub struct TTYSwitchSerialPort {
@@ -15,14 +15,42 @@ pub struct TTYSwitchSerialPort {
 }
 
 impl TTYSwitchSerialPort {
-    pub async fn new<P: AsRef<Path>>(port: P, baudrate: u32) -> color_eyre::Result<Self> {
+    pub async fn new(config: &TTYConfig) -> color_eyre::Result<Self> {
+        let data_bits = match config.data_bits {
+            5 => DataBits::Five,
+            6 => DataBits::Six,
+            7 => DataBits::Seven,
+            8 => DataBits::Eight,
+            n => return Err(eyre!("unsupported number of data bits: {n}")),
+        };
+        let parity = match config.parity {
+            SerialParity::None => Parity::None,
+            SerialParity::Odd => Parity::Odd,
+            SerialParity::Even => Parity::Even,
+            p => return Err(eyre!("unsupported parity for a local tty: {p:?}")),
+        };
+        let stop_bits = match config.stop_bits {
+            SerialStopBits::One => StopBits::One,
+            SerialStopBits::Two => StopBits::Two,
+            s => return Err(eyre!("unsupported stop bits for a local tty: {s:?}")),
+        };
+        let flow_control = match config.flow_control {
+            SerialFlowControl::None => FlowControl::None,
+            SerialFlowControl::XonXoff => FlowControl::Software,
+            SerialFlowControl::Hardware => FlowControl::Hardware,
+        };
         let builder = tokio_serial::new(
-            port.as_ref()
+            config
+                .path
                 .as_os_str()
                 .to_str()
                 .ok_or_eyre("failed to convert path")?,
-            baudrate,
-        );
+            config.baudrate.0,
+        )
+        .data_bits(data_bits)
+        .parity(parity)
+        .stop_bits(stop_bits)
+        .flow_control(flow_control);
         let stream = SerialStream::open(&builder)?;
         Ok(TTYSwitchSerialPort { stream })
     }
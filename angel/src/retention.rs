@@ -0,0 +1,216 @@
+use chrono::{DateTime, Utc};
+use cthulhu_config::angel::LogRetentionConfig;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// Finished jobs in `log_dir`, one JSON object per line.
+const INDEX_FILE: &str = "index.jsonl";
+
+/// How often [`prune_periodically`] applies the retention policy.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// All ports share the index.
+static INDEX_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// Where the logs of a finished job ended up.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogIndexEntry {
+    pub port: String,
+    pub serial_number: Option<String>,
+    pub job_started: Option<DateTime<Utc>>,
+    pub job_ended: DateTime<Utc>,
+    pub files: Vec<PathBuf>,
+}
+
+/// Compress the logs of a finished job if configured, add it to the index and apply the retention policy.
+pub async fn archive_job(
+    log_dir: &Path,
+    retention: &LogRetentionConfig,
+    mut entry: LogIndexEntry,
+) -> color_eyre::Result<()> {
+    if retention.compress {
+        let mut files = Vec::with_capacity(entry.files.len());
+        for file in entry.files {
+            // The certificate is tiny and should stay readable as is.
+            if file.to_string_lossy().ends_with(".certificate.json") {
+                files.push(file);
+            } else {
+                files.push(tokio::task::spawn_blocking(move || gzip_file(&file)).await??);
+            }
+        }
+        entry.files = files;
+    }
+
+    let _guard = INDEX_LOCK.lock().await;
+    let mut entries = read_index(log_dir).await?;
+    entries.push(entry);
+    let entries = apply_retention(retention, entries).await;
+    write_index(log_dir, &entries).await
+}
+
+/// Apply the retention policy without adding a job, so that an idle angel cleans up too.
+pub async fn prune(log_dir: &Path, retention: &LogRetentionConfig) -> color_eyre::Result<()> {
+    let _guard = INDEX_LOCK.lock().await;
+    let entries = read_index(log_dir).await?;
+    let count = entries.len();
+    let entries = apply_retention(retention, entries).await;
+    if entries.len() != count {
+        write_index(log_dir, &entries).await?;
+    }
+    Ok(())
+}
+
+/// Run [`prune`] now and every [`PRUNE_INTERVAL`] after, old logs expire even when no job finishes.
+pub async fn prune_periodically(log_dir: PathBuf, retention: LogRetentionConfig) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = prune(&log_dir, &retention).await {
+            warn!("Applying the log retention policy failed: {e:?}");
+        }
+    }
+}
+
+async fn read_index(log_dir: &Path) -> color_eyre::Result<Vec<LogIndexEntry>> {
+    let data = match tokio::fs::read_to_string(log_dir.join(INDEX_FILE)).await {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut entries = Vec::new();
+    for line in data.lines().filter(|l| !l.trim().is_empty()) {
+        match serde_json::from_str(line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => warn!("Dropping malformed log index entry: {e}"),
+        }
+    }
+    Ok(entries)
+}
+
+async fn write_index(log_dir: &Path, entries: &[LogIndexEntry]) -> color_eyre::Result<()> {
+    let mut data = Vec::new();
+    for entry in entries {
+        data.extend(serde_json::to_vec(entry)?);
+        data.push(b'\n');
+    }
+    let index_file = log_dir.join(INDEX_FILE);
+    let tmp_file = index_file.with_extension("jsonl.tmp");
+    tokio::fs::write(&tmp_file, data).await?;
+    tokio::fs::rename(&tmp_file, &index_file).await?;
+    Ok(())
+}
+
+/// Delete the logs of jobs that are too old, then of the oldest jobs until the rest fits.
+///
+/// The most recent job is always kept, so a single huge job does not wipe itself out.
+async fn apply_retention(
+    retention: &LogRetentionConfig,
+    mut entries: Vec<LogIndexEntry>,
+) -> Vec<LogIndexEntry> {
+    entries.sort_by_key(|e| e.job_ended);
+    let mut expired = 0;
+    if let Some(max_age_days) = retention.max_age_days {
+        let cutoff = Utc::now() - chrono::Duration::days(max_age_days as i64);
+        expired = entries.iter().take_while(|e| e.job_ended < cutoff).count();
+    }
+    if let Some(max_total_size_mb) = retention.max_total_size_mb {
+        let mut sizes = Vec::with_capacity(entries.len());
+        for entry in entries.iter() {
+            let mut size = 0;
+            for file in entry.files.iter() {
+                size += tokio::fs::metadata(file).await.map(|m| m.len()).unwrap_or_default();
+            }
+            sizes.push(size);
+        }
+        let mut total: u64 = sizes[expired.min(sizes.len())..].iter().sum();
+        while total > max_total_size_mb * 1024 * 1024 && expired < entries.len() {
+            total -= sizes[expired];
+            expired += 1;
+        }
+    }
+    let expired = expired.min(entries.len().saturating_sub(1));
+
+    for entry in entries.drain(..expired) {
+        info!("Deleting the logs of the {} job that ended {}.", entry.port, entry.job_ended);
+        for file in entry.files.iter() {
+            match tokio::fs::remove_file(file).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => warn!("Unable to delete {file:?}: {e}"),
+            }
+        }
+    }
+    entries
+}
+
+/// Replace a file with its gzipped version, returns the new path.
+fn gzip_file(file: &Path) -> color_eyre::Result<PathBuf> {
+    let mut gz_file = file.as_os_str().to_owned();
+    gz_file.push(".gz");
+    let gz_file = PathBuf::from(gz_file);
+    let tmp_file = gz_file.with_extension("gz.tmp");
+    let mut encoder = GzEncoder::new(File::create(&tmp_file)?, Compression::default());
+    std::io::copy(&mut File::open(file)?, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    std::fs::rename(&tmp_file, &gz_file)?;
+    std::fs::remove_file(file)?;
+    Ok(gz_file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn gzip_roundtrip() {
+        let file = std::env::temp_dir().join(format!("cthulhu-gzip-{}.raw.log", std::process::id()));
+        let data = b"Booting [/kernel]...\r\n".repeat(100);
+        std::fs::write(&file, &data).unwrap();
+        let gz_file = gzip_file(&file).unwrap();
+        assert!(!file.exists());
+        assert_eq!(gz_file.extension().unwrap(), "gz");
+        let mut inflated = Vec::new();
+        flate2::read::GzDecoder::new(File::open(&gz_file).unwrap())
+            .read_to_end(&mut inflated)
+            .unwrap();
+        assert_eq!(inflated, data);
+        std::fs::remove_file(&gz_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn retention() {
+        let dir = std::env::temp_dir().join(format!("cthulhu-retention-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let mut entries = Vec::new();
+        for (i, days_ago) in [40, 3, 2, 1].into_iter().enumerate() {
+            let file = dir.join(format!("job{i}.raw.log"));
+            tokio::fs::write(&file, vec![0u8; 600 * 1024]).await.unwrap();
+            entries.push(LogIndexEntry {
+                port: "S1".to_string(),
+                serial_number: None,
+                job_started: None,
+                job_ended: Utc::now() - chrono::Duration::days(days_ago),
+                files: vec![file],
+            });
+        }
+        let retention = LogRetentionConfig {
+            max_age_days: Some(30),
+            max_total_size_mb: Some(1),
+            compress: false,
+        };
+        let kept = apply_retention(&retention, entries).await;
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].files[0], dir.join("job3.raw.log"));
+        assert!(!dir.join("job0.raw.log").exists());
+        assert!(!dir.join("job2.raw.log").exists());
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,85 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Terminal size recorded in the header, serial consoles assume 80x24.
+pub const WIDTH: u16 = 80;
+pub const HEIGHT: u16 = 24;
+
+#[derive(Serialize)]
+struct Header<'a> {
+    version: u8,
+    width: u16,
+    height: u16,
+    timestamp: i64,
+    title: &'a str,
+}
+
+/// The first line of an asciinema v2 cast file.
+pub fn header(started: DateTime<Utc>, title: &str) -> String {
+    let header = Header {
+        version: 2,
+        width: WIDTH,
+        height: HEIGHT,
+        timestamp: started.timestamp(),
+        title,
+    };
+    line(&header)
+}
+
+/// A marker event, players show these on the timeline.
+pub fn marker(time: f64, label: &str) -> String {
+    line(&(round(time), "m", label))
+}
+
+/// Turns serial output into cast events.
+///
+/// Events carry text, so a multibyte character split over two reads is held back until it is complete.
+#[derive(Debug, Default)]
+pub struct CastEncoder {
+    pending: Vec<u8>,
+}
+
+impl CastEncoder {
+    /// The output event for `data`, if there is anything to show yet.
+    pub fn output(&mut self, time: f64, data: &[u8]) -> Option<String> {
+        self.pending.extend(data);
+        let complete = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            // Only wait for the rest of a character, garbage is passed on as is.
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => self.pending.len(),
+        };
+        if complete == 0 {
+            return None;
+        }
+        let text = String::from_utf8_lossy(&self.pending[..complete]).into_owned();
+        self.pending.drain(..complete);
+        Some(line(&(round(time), "o", text)))
+    }
+}
+
+/// Millisecond precision is plenty and keeps the files small.
+fn round(time: f64) -> f64 {
+    (time * 1000.0).round() / 1000.0
+}
+
+fn line<T: Serialize>(value: &T) -> String {
+    let mut line = serde_json::to_string(value).expect("cast events always serialize");
+    line.push('\n');
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events() {
+        let mut encoder = CastEncoder::default();
+        assert_eq!(encoder.output(0.5, b"login:"), Some("[0.5,\"o\",\"login:\"]\n".to_string()));
+        // "é" split over two reads.
+        assert_eq!(encoder.output(1.0, &[b'a', 0xc3]), Some("[1.0,\"o\",\"a\"]\n".to_string()));
+        assert_eq!(encoder.output(1.0004, &[0xa9]), Some("[1.0,\"o\",\"é\"]\n".to_string()));
+        assert_eq!(marker(2.25, "Wipe"), "[2.25,\"m\",\"Wipe\"]\n");
+    }
+}
@@ -0,0 +1,191 @@
+use crate::devinfo::DeviceInformation;
+use crate::job::JobData;
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Context, eyre};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Proof that a device was sanitised, built from the job data once a job finishes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErasureCertificate {
+    pub serial_number: Option<String>,
+    pub vendor: Option<String>,
+    pub model: Option<String>,
+    pub software_version: Option<String>,
+    /// The states the job went through, this is how the device was wiped.
+    pub wipe_method: Vec<(DateTime<Utc>, String)>,
+    pub verification: WipeVerification,
+    pub job_started: Option<DateTime<Utc>>,
+    pub job_ended: Option<DateTime<Utc>>,
+    /// The port the device was connected to.
+    pub port_id: String,
+    /// Hex encoded SHA-256 of the raw serial transcript.
+    pub transcript_sha256: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum WipeVerification {
+    Verified,
+    Failed,
+    Unverified,
+}
+
+/// A certificate with a hex encoded ed25519 signature over its [canonical bytes](ErasureCertificate::canonical_bytes).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedErasureCertificate {
+    pub certificate: ErasureCertificate,
+    pub signature: Option<String>,
+    pub public_key: Option<String>,
+}
+
+impl ErasureCertificate {
+    pub fn from_job(data: &JobData, transcript_sha256: Option<String>) -> Self {
+        let mut certificate = Self {
+            serial_number: None,
+            vendor: None,
+            model: None,
+            software_version: None,
+            wipe_method: data.state_history.clone(),
+            verification: WipeVerification::Unverified,
+            job_started: data.job_started,
+            job_ended: data.job_ended,
+            port_id: data.label.clone(),
+            transcript_sha256,
+        };
+        for item in data.info_items.iter() {
+            match item {
+                DeviceInformation::SerialNumber(s) => certificate.serial_number = Some(s.clone()),
+                DeviceInformation::Vendor(s) => certificate.vendor = Some(s.clone()),
+                DeviceInformation::Model(s) => certificate.model = Some(s.clone()),
+                DeviceInformation::SoftwareVersion(s) => {
+                    certificate.software_version = Some(s.clone())
+                }
+                DeviceInformation::WipeVerified => {
+                    certificate.verification = WipeVerification::Verified
+                }
+                _ => {}
+            }
+        }
+        // A failed check always wins over a successful one.
+        if data.info_items.contains(&DeviceInformation::DidNotWipe) {
+            certificate.verification = WipeVerification::Failed;
+        }
+        certificate
+    }
+}
+
+impl ErasureCertificate {
+    /// The bytes that get signed: Compact JSON with the keys of every object sorted.
+    ///
+    /// For the strings and integers a certificate holds this is the RFC 8785 form, so a verifier
+    /// can rebuild it from the JSON it received no matter how that was formatted.
+    pub fn canonical_bytes(&self) -> color_eyre::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        write_canonical(&serde_json::to_value(self)?, &mut out)?;
+        Ok(out)
+    }
+}
+
+fn write_canonical(value: &Value, out: &mut Vec<u8>) -> serde_json::Result<()> {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            out.push(b'{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                serde_json::to_writer(&mut *out, key)?;
+                out.push(b':');
+                write_canonical(&map[key], out)?;
+            }
+            out.push(b'}');
+        }
+        Value::Array(items) => {
+            out.push(b'[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_canonical(item, out)?;
+            }
+            out.push(b']');
+        }
+        _ => serde_json::to_writer(&mut *out, value)?,
+    }
+    Ok(())
+}
+
+pub fn sign_certificate(
+    certificate: ErasureCertificate,
+    key: Option<&SigningKey>,
+) -> color_eyre::Result<SignedErasureCertificate> {
+    let (signature, public_key) = if let Some(key) = key {
+        let signature = key.sign(&certificate.canonical_bytes()?);
+        (
+            Some(hex::encode(signature.to_bytes())),
+            Some(hex::encode(key.verifying_key().to_bytes())),
+        )
+    } else {
+        (None, None)
+    };
+    Ok(SignedErasureCertificate {
+        certificate,
+        signature,
+        public_key,
+    })
+}
+
+/// Checks the signature against the public key it was published with.
+///
+/// This proves the certificate was not altered, whoever relies on it still has to trust that key.
+pub fn verify_certificate(signed: &SignedErasureCertificate) -> color_eyre::Result<()> {
+    let (Some(signature), Some(public_key)) = (&signed.signature, &signed.public_key) else {
+        return Err(eyre!("certificate is not signed"));
+    };
+    let mut key = [0u8; 32];
+    hex::decode_to_slice(public_key, &mut key).wrap_err("invalid public key")?;
+    let mut bytes = [0u8; 64];
+    hex::decode_to_slice(signature, &mut bytes).wrap_err("invalid signature")?;
+    VerifyingKey::from_bytes(&key)
+        .wrap_err("invalid public key")?
+        .verify_strict(
+            &signed.certificate.canonical_bytes()?,
+            &Signature::from_bytes(&bytes),
+        )
+        .wrap_err("signature does not match")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_and_verify() -> color_eyre::Result<()> {
+        let mut job = JobData::with_label("S1");
+        job.info_items.insert(DeviceInformation::SerialNumber("ABC123".to_string()));
+        let certificate = ErasureCertificate::from_job(&job, Some("00ff".to_string()));
+        assert!(
+            certificate
+                .canonical_bytes()?
+                .starts_with(b"{\"job_ended\":null,\"job_started\":null,\"model\":null,\"port_id\":\"S1\",")
+        );
+
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let mut signed = sign_certificate(certificate, Some(&key))?;
+        verify_certificate(&signed)?;
+
+        // A round trip through differently formatted JSON does not matter.
+        let pretty = serde_json::to_string_pretty(&signed)?;
+        verify_certificate(&serde_json::from_str(&pretty)?)?;
+
+        signed.certificate.serial_number = Some("XYZ789".to_string());
+        assert!(verify_certificate(&signed).is_err());
+
+        let unsigned = sign_certificate(signed.certificate, None)?;
+        assert!(verify_certificate(&unsigned).is_err());
+        Ok(())
+    }
+}
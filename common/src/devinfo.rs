@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::fmt::{Debug, Display, Formatter};
 use serde::{Deserialize, Serialize};
 
@@ -27,6 +28,26 @@ pub enum DeviceInformation {
     BadFlashBlock,
     SoftwareUpdatePerformed,
     DidNotWipe,
+    WipeVerified,
+    PowerSupplies(Vec<ComponentStatus>),
+    Fans(Vec<ComponentStatus>),
+    Transceivers(Vec<Transceiver>),
+    Licenses(Vec<String>),
+    PortCount(u32),
+    FaultyPowerSupply,
+    FaultyFan,
+    /// The serial port connection dropped during the job, for this many seconds in total.
+    SerialConnectionLost(u64),
+    /// Baud rate found by autobaud detection.
+    Baudrate(u32),
+    /// The watchdog ended the job, it was stuck in this state.
+    JobTimedOut(String),
+    /// Free-form item, lets state files record new findings without a new variant.
+    Custom {
+        key: String,
+        value: Option<String>,
+        level: DeviceInformationType,
+    },
 }
 
 impl DeviceInformation {
@@ -56,19 +77,163 @@ impl DeviceInformation {
             DeviceInformation::BootloaderVersion(_) => DeviceInformationType::Info,
             DeviceInformation::SoftwareUpdatePerformed => DeviceInformationType::Warning,
             DeviceInformation::DidNotWipe => DeviceInformationType::Error,
+            DeviceInformation::WipeVerified => DeviceInformationType::Info,
+            DeviceInformation::PowerSupplies(_) => DeviceInformationType::Info,
+            DeviceInformation::Fans(_) => DeviceInformationType::Info,
+            DeviceInformation::Transceivers(_) => DeviceInformationType::Info,
+            DeviceInformation::Licenses(_) => DeviceInformationType::Info,
+            DeviceInformation::PortCount(_) => DeviceInformationType::Info,
+            DeviceInformation::FaultyPowerSupply => DeviceInformationType::Warning,
+            DeviceInformation::FaultyFan => DeviceInformationType::Warning,
+            DeviceInformation::SerialConnectionLost(_) => DeviceInformationType::Warning,
+            DeviceInformation::Baudrate(_) => DeviceInformationType::Info,
+            DeviceInformation::JobTimedOut(_) => DeviceInformationType::Error,
+            DeviceInformation::Custom { level, .. } => *level,
         }
     }
+
+    /// Name of this kind of item as used in a [`SeverityPolicy`], custom items all share `Custom`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DeviceInformation::SerialNumber(_) => "SerialNumber",
+            DeviceInformation::MacAddress(_) => "MacAddress",
+            DeviceInformation::SoftwareVersion(_) => "SoftwareVersion",
+            DeviceInformation::BootloaderVersion(_) => "BootloaderVersion",
+            DeviceInformation::Model(_) => "Model",
+            DeviceInformation::Vendor(_) => "Vendor",
+            DeviceInformation::AttemptedToFixFilesystemIssues => "AttemptedToFixFilesystemIssues",
+            DeviceInformation::FailedToEnterSingleUserMode => "FailedToEnterSingleUserMode",
+            DeviceInformation::ReadonlyFlash => "ReadonlyFlash",
+            DeviceInformation::SCSIErrors => "SCSIErrors",
+            DeviceInformation::KeptHostname => "KeptHostname",
+            DeviceInformation::Aborted => "Aborted",
+            DeviceInformation::BootLoop => "BootLoop",
+            DeviceInformation::UnableToLoadAKernel => "UnableToLoadAKernel",
+            DeviceInformation::AlternateImage => "AlternateImage",
+            DeviceInformation::StrangeCLIPrompt => "StrangeCLIPrompt",
+            DeviceInformation::OSCorruption => "OSCorruption",
+            DeviceInformation::ProvisioningFailed => "ProvisioningFailed",
+            DeviceInformation::ProvisioningSuccess => "ProvisioningSuccess",
+            DeviceInformation::LoopDetected => "LoopDetected",
+            DeviceInformation::RaceConditionFailed => "RaceConditionFailed",
+            DeviceInformation::BadFlashBlock => "BadFlashBlock",
+            DeviceInformation::SoftwareUpdatePerformed => "SoftwareUpdatePerformed",
+            DeviceInformation::DidNotWipe => "DidNotWipe",
+            DeviceInformation::WipeVerified => "WipeVerified",
+            DeviceInformation::PowerSupplies(_) => "PowerSupplies",
+            DeviceInformation::Fans(_) => "Fans",
+            DeviceInformation::Transceivers(_) => "Transceivers",
+            DeviceInformation::Licenses(_) => "Licenses",
+            DeviceInformation::PortCount(_) => "PortCount",
+            DeviceInformation::FaultyPowerSupply => "FaultyPowerSupply",
+            DeviceInformation::FaultyFan => "FaultyFan",
+            DeviceInformation::SerialConnectionLost(_) => "SerialConnectionLost",
+            DeviceInformation::Baudrate(_) => "Baudrate",
+            DeviceInformation::JobTimedOut(_) => "JobTimedOut",
+            DeviceInformation::Custom { .. } => "Custom",
+        }
+    }
+
+    /// Is this item part of the structured hardware inventory?
+    pub fn is_inventory(&self) -> bool {
+        matches!(
+            self,
+            DeviceInformation::PowerSupplies(_)
+                | DeviceInformation::Fans(_)
+                | DeviceInformation::Transceivers(_)
+                | DeviceInformation::Licenses(_)
+                | DeviceInformation::PortCount(_)
+        )
+    }
 }
 
 impl Display for DeviceInformation {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        Debug::fmt(&self, f)
+        match self {
+            DeviceInformation::Custom { key, value: Some(value), .. } => write!(f, "{key}: {value}"),
+            DeviceInformation::Custom { key, value: None, .. } => write!(f, "{key}"),
+            _ => Debug::fmt(&self, f),
+        }
+    }
+}
+
+/// Status of a replaceable component, such as a power supply or a fan.
+#[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct ComponentStatus {
+    pub name: String,
+    pub status: String,
+}
+
+impl ComponentStatus {
+    /// Empty slots are fine, anything else that does not report OK is not.
+    pub fn is_faulty(&self) -> bool {
+        !matches!(
+            self.status.to_lowercase().as_str(),
+            "ok" | "present" | "absent" | "not inserted" | "not present"
+        )
     }
 }
 
-#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+/// An optic installed in one of the ports of the device.
+#[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct Transceiver {
+    pub port: String,
+    pub model: String,
+    pub serial: String,
+}
+
+#[derive(Copy, Clone, Debug, Default, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum DeviceInformationType {
+    #[default]
     Info,
     Warning,
     Error,
 }
+
+/// Per-event overrides of the built-in severities, keyed by [`DeviceInformation::kind`].
+///
+/// Custom items are keyed by their own key in a separate `Custom` table, so they can never
+/// shadow a built-in kind.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SeverityPolicy {
+    #[serde(flatten)]
+    pub kinds: BTreeMap<String, DeviceInformationType>,
+    #[serde(rename = "Custom", default)]
+    pub custom: BTreeMap<String, DeviceInformationType>,
+}
+
+impl SeverityPolicy {
+    pub fn get_type(&self, item: &DeviceInformation) -> DeviceInformationType {
+        let level = match item {
+            DeviceInformation::Custom { key, .. } => self.custom.get(key),
+            _ => self.kinds.get(item.kind()),
+        };
+        level.copied().unwrap_or_else(|| item.get_type())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn severity_policy() {
+        let policy: SeverityPolicy = serde_json::from_value(serde_json::json!({
+            "KeptHostname": "Info",
+            "Custom": {"SerialNumber": "Error"},
+        }))
+        .unwrap();
+        assert_eq!(policy.get_type(&DeviceInformation::KeptHostname), DeviceInformationType::Info);
+        assert_eq!(
+            policy.get_type(&DeviceInformation::SerialNumber("ABC123".to_string())),
+            DeviceInformationType::Info
+        );
+        let custom = DeviceInformation::Custom {
+            key: "SerialNumber".to_string(),
+            value: None,
+            level: DeviceInformationType::Warning,
+        };
+        assert_eq!(custom.kind(), "Custom");
+        assert_eq!(policy.get_type(&custom), DeviceInformationType::Error);
+    }
+}
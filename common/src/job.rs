@@ -1,15 +1,27 @@
-use crate::devinfo::{DeviceInformation, DeviceInformationType};
+use crate::certificate::SignedErasureCertificate;
+use crate::devinfo::{DeviceInformation, DeviceInformationType, SeverityPolicy};
 use chrono::{DateTime, TimeDelta, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::fmt::Display;
 use std::ops::Add;
-use crate::status::JobUpdate;
+use crate::status::{AngelStatus, JobUpdate, OperatorAction, RejectedActiveStates, Takeover};
 
 fn variant_eq<T>(a: &T, b: &T) -> bool {
     std::mem::discriminant(a) == std::mem::discriminant(b)
 }
 
+/// Custom items are distinguished by their key, all other items by their variant.
+fn same_item(a: &DeviceInformation, b: &DeviceInformation) -> bool {
+    match (a, b) {
+        (DeviceInformation::Custom { key: a, .. }, DeviceInformation::Custom { key: b, .. }) => a == b,
+        _ => variant_eq(a, b),
+    }
+}
+
+/// Prefix of operator actions in the state history, these are not states.
+pub const OPERATOR_ACTION_PREFIX: &str = "Operator: ";
+
 /// Current and historical data of a job.
 #[derive(Default, Debug, Serialize, Deserialize, Clone)]
 pub struct JobData {
@@ -22,6 +34,28 @@ pub struct JobData {
     pub state_history: Vec<(DateTime<Utc>, String)>,
     /// List of device information
     pub info_items: HashSet<DeviceInformation>,
+    /// Erasure certificate, issued once the job has finished
+    #[serde(default)]
+    pub certificate: Option<Box<SignedErasureCertificate>>,
+    /// Since when is the connection to the serial port down?
+    #[serde(default)]
+    pub port_disconnected: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub angel_status: AngelStatus,
+    #[serde(default)]
+    pub takeover: Option<Takeover>,
+    /// The state machine only moves when stepped.
+    #[serde(default)]
+    pub paused: bool,
+    /// State files the port runs, this belongs to the port and outlives the job.
+    #[serde(default)]
+    pub active_states: Vec<String>,
+    /// State files the port switches to once the current job is done.
+    #[serde(default)]
+    pub next_active_states: Option<Vec<String>>,
+    /// The last switch of state files that was refused, until one succeeds.
+    #[serde(default)]
+    pub rejected_active_states: Option<Box<RejectedActiveStates>>,
 }
 
 impl JobData {
@@ -32,6 +66,14 @@ impl JobData {
             job_ended: None,
             state_history: Vec::new(),
             info_items: HashSet::new(),
+            certificate: None,
+            port_disconnected: None,
+            angel_status: AngelStatus::Running,
+            takeover: None,
+            paused: false,
+            active_states: Vec::new(),
+            next_active_states: None,
+            rejected_active_states: None,
         }
     }
 
@@ -40,10 +82,12 @@ impl JobData {
         self.job_ended = None;
         self.state_history = Vec::new();
         self.info_items = HashSet::new();
+        self.certificate = None;
+        self.paused = false;
     }
 
     pub fn add_info_item(&mut self, i: DeviceInformation) {
-        self.info_items.retain(|x| !variant_eq(x, &i));
+        self.info_items.retain(|x| !same_item(x, &i));
         self.info_items.insert(i);
     }
 
@@ -65,29 +109,86 @@ impl JobData {
             JobUpdate::JobFullData(d) => {
                 *self = d;
             }
+            JobUpdate::JobCertificate(c) => {
+                self.certificate = Some(c);
+            }
+            JobUpdate::PortDisconnected(d) => {
+                self.port_disconnected = Some(d);
+            }
+            JobUpdate::PortReconnected(_) => {
+                self.port_disconnected = None;
+            }
+            JobUpdate::AngelStatus(s) => {
+                self.angel_status = s;
+            }
+            JobUpdate::PortTakenOver(t) => {
+                self.takeover = Some(t);
+            }
+            JobUpdate::PortReleased(_) => {
+                self.takeover = None;
+            }
+            JobUpdate::OperatorAction(d, action) => {
+                match action {
+                    OperatorAction::Pause => self.paused = true,
+                    OperatorAction::Resume => self.paused = false,
+                    _ => {}
+                }
+                self.state_history.push((d, format!("{OPERATOR_ACTION_PREFIX}{action}")));
+            }
+            JobUpdate::ActiveStates { current, next } => {
+                self.active_states = current;
+                self.next_active_states = next;
+                self.rejected_active_states = None;
+            }
+            JobUpdate::ActiveStatesRejected(r) => {
+                self.rejected_active_states = Some(r);
+            }
         }
     }
 
     pub fn get_current_stage(&self) -> Option<&str> {
-        self.state_history.last().map(|(_, s)| s.as_str())
+        self.state_history
+            .iter()
+            .rev()
+            .map(|(_, s)| s.as_str())
+            .find(|s| !s.starts_with(OPERATOR_ACTION_PREFIX))
     }
 
     pub fn get_last_updated(&self) -> Option<DateTime<Utc>> {
         self.state_history.last().map(|(s, _)| s.clone())
     }
 
-    pub fn get_max_information_type(&self) -> DeviceInformationType {
-        self.info_items.iter().map(|i| i.get_type())
+    pub fn get_max_information_type(&self, policy: &SeverityPolicy) -> DeviceInformationType {
+        self.info_items.iter().map(|i| policy.get_type(i))
             .max()
             .unwrap_or(DeviceInformationType::Warning)
     }
-    pub fn get_status(&self) -> JobStatus {
+    pub fn get_status(&self, policy: &SeverityPolicy) -> JobStatus {
+        let status = self.get_job_status(policy);
+        match self.angel_status {
+            AngelStatus::GoingDown => JobStatus::Offline,
+            AngelStatus::Lost => JobStatus::Fatal,
+            AngelStatus::Draining if status.is_idle() => JobStatus::Drained,
+            _ => status,
+        }
+    }
+
+    fn get_job_status(&self, policy: &SeverityPolicy) -> JobStatus {
+        if self.takeover.is_some() {
+            return JobStatus::TakenOver;
+        }
+        if self.paused {
+            return JobStatus::Paused;
+        }
+        if self.port_disconnected.is_some() {
+            return JobStatus::PortDisconnected;
+        }
         if let Some(current_state) = self.get_current_stage() {
             match current_state {
                 "Init" => JobStatus::Idle,
                 "SwitchDetect" => JobStatus::Idle,
                 "JobFinished" => {
-                    match self.get_max_information_type() {
+                    match self.get_max_information_type(policy) {
                         DeviceInformationType::Info => JobStatus::FinishSuccess,
                         DeviceInformationType::Warning => JobStatus::FinishWarning,
                         DeviceInformationType::Error => JobStatus::FinishError,
@@ -127,8 +228,18 @@ pub enum JobStatus {
     Busy,
     /// This job is taking too long.
     RunningLong,
-    /// This thread has crashed.
+    /// This thread has crashed, or the angel serving it has disappeared.
     Fatal,
+    /// The connection to the serial port is down, the job continues once it is back.
+    PortDisconnected,
+    /// An operator has taken over the port.
+    TakenOver,
+    /// An operator has paused the state machine.
+    Paused,
+    /// The angel has been drained and does not start new jobs.
+    Drained,
+    /// The angel has shut down.
+    Offline,
 }
 
 impl JobStatus {
@@ -138,6 +249,8 @@ impl JobStatus {
             JobStatus::FinishSuccess => true,
             JobStatus::FinishWarning => true,
             JobStatus::FinishError => true,
+            JobStatus::Drained => true,
+            JobStatus::Offline => true,
             _ => false,
         }
     }
@@ -162,6 +275,11 @@ impl Display for JobStatus {
             JobStatus::Busy => write!(f, "⏳"),
             JobStatus::RunningLong => write!(f, "⏰"),
             JobStatus::Fatal => write!(f, "😵"),
+            JobStatus::PortDisconnected => write!(f, "🔌"),
+            JobStatus::TakenOver => write!(f, "🧑‍🔧"),
+            JobStatus::Paused => write!(f, "⏸️"),
+            JobStatus::Drained => write!(f, "🚧"),
+            JobStatus::Offline => write!(f, "💤"),
         }
     }
 }
@@ -1,4 +1,9 @@
+pub mod asciicast;
+pub mod certificate;
 pub mod devinfo;
+pub mod metrics;
+pub mod mqtt;
+pub mod serial;
 //pub mod stages;
 pub mod status;
 
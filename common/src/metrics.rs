@@ -0,0 +1,278 @@
+use crate::devinfo::{DeviceInformation, DeviceInformationType, SeverityPolicy};
+use crate::job::{JobData, OPERATOR_ACTION_PREFIX};
+use crate::status::JobUpdate;
+use chrono::{DateTime, Utc};
+use prometheus_client::encoding::{EncodeLabelSet, EncodeLabelValue, LabelValueEncoder};
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::registry::{Registry, Unit};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
+
+/// Content type of [`Metrics::render`].
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// A label value, escaped the way the text format wants it since the encoder writes values as is.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct Escaped(String);
+
+impl EncodeLabelValue for Escaped {
+    fn encode(&self, encoder: &mut LabelValueEncoder) -> Result<(), std::fmt::Error> {
+        for c in self.0.chars() {
+            match c {
+                '\\' => encoder.write_str("\\\\")?,
+                '"' => encoder.write_str("\\\"")?,
+                '\n' => encoder.write_str("\\n")?,
+                c => encoder.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<&str> for Escaped {
+    fn from(value: &str) -> Self {
+        Escaped(value.to_string())
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct PortLabels {
+    port: Escaped,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct StateLabels {
+    state: Escaped,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct ConsumerLabels {
+    consumer: Escaped,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct OutcomeLabels {
+    outcome: Escaped,
+    vendor: Escaped,
+    model: Escaped,
+}
+
+struct MetricsInner {
+    severity_policy: SeverityPolicy,
+    /// What we know of every port, built from the same updates heaven gets.
+    jobs: BTreeMap<String, JobData>,
+}
+
+/// Counters for the job updates and serial output passing through, rendered in the OpenMetrics text format.
+#[derive(Clone)]
+pub struct Metrics {
+    inner: Arc<Mutex<MetricsInner>>,
+    registry: Arc<Registry>,
+    ports: Gauge,
+    active_ports: Gauge,
+    jobs_started: Family<PortLabels, Counter>,
+    jobs_finished: Family<OutcomeLabels, Counter>,
+    state_seconds: Family<StateLabels, Counter<f64, AtomicU64>>,
+    state_entered: Family<StateLabels, Counter>,
+    loop_detections: Family<PortLabels, Counter>,
+    serial_bytes: Family<PortLabels, Counter>,
+    lagged: Family<ConsumerLabels, Counter>,
+}
+
+impl Metrics {
+    pub fn new(severity_policy: SeverityPolicy) -> Self {
+        let mut registry = Registry::default();
+        let ports = Gauge::default();
+        registry.register("cthulhu_ports", "Known ports", ports.clone());
+        let active_ports = Gauge::default();
+        registry.register("cthulhu_active_ports", "Ports with a job that has not ended yet", active_ports.clone());
+        let jobs_started = Family::default();
+        registry.register("cthulhu_jobs_started", "Jobs started, by port", jobs_started.clone());
+        let jobs_finished = Family::default();
+        registry.register(
+            "cthulhu_jobs_finished",
+            "Jobs that have ended, by outcome and device",
+            jobs_finished.clone(),
+        );
+        let state_seconds = Family::default();
+        registry.register_with_unit("cthulhu_state", "Time spent in each state", Unit::Seconds, state_seconds.clone());
+        let state_entered = Family::default();
+        registry.register("cthulhu_state_entered", "Transitions into each state", state_entered.clone());
+        let loop_detections = Family::default();
+        registry.register(
+            "cthulhu_loop_detections",
+            "Jobs ended because the state machine went round in circles, by port",
+            loop_detections.clone(),
+        );
+        let serial_bytes = Family::default();
+        registry.register(
+            "cthulhu_serial_bytes",
+            "Bytes received from the serial port, by port",
+            serial_bytes.clone(),
+        );
+        let lagged = Family::default();
+        registry.register(
+            "cthulhu_broadcast_lagged",
+            "Messages skipped by consumers that could not keep up with MQTT",
+            lagged.clone(),
+        );
+
+        Self {
+            inner: Arc::new(Mutex::new(MetricsInner {
+                severity_policy,
+                jobs: BTreeMap::new(),
+            })),
+            registry: Arc::new(registry),
+            ports,
+            active_ports,
+            jobs_started,
+            jobs_finished,
+            state_seconds,
+            state_entered,
+            loop_detections,
+            serial_bytes,
+            lagged,
+        }
+    }
+
+    pub fn job_update(&self, port: &str, update: &JobUpdate) {
+        let mut inner = self.inner.lock().unwrap();
+        let job = inner
+            .jobs
+            .entry(port.to_string())
+            .or_insert_with(|| JobData::with_label(port));
+        // The time spent in the previous state is only known once the next one is entered, or the job ends.
+        let previous = job
+            .state_history
+            .iter()
+            .rev()
+            .find(|(_, s)| !s.starts_with(OPERATOR_ACTION_PREFIX))
+            .cloned();
+        let running = job.job_started.is_some() && job.job_ended.is_none();
+        job.update(update.clone());
+
+        match update {
+            JobUpdate::JobStart(_) => {
+                self.jobs_started.get_or_create(&PortLabels { port: port.into() }).inc();
+            }
+            // A job that had already ended is only being republished.
+            JobUpdate::JobEnd(at) if running => {
+                self.state_time(previous, at);
+                self.job_finished(&inner, port);
+            }
+            JobUpdate::JobStageTransition(at, state) => {
+                self.state_time(previous, at);
+                self.state_entered
+                    .get_or_create(&StateLabels { state: state.as_str().into() })
+                    .inc();
+            }
+            JobUpdate::JobNewInfoItem(DeviceInformation::LoopDetected) => {
+                self.loop_detections.get_or_create(&PortLabels { port: port.into() }).inc();
+            }
+            _ => {}
+        }
+
+        let active = inner
+            .jobs
+            .values()
+            .filter(|j| j.job_started.is_some() && j.job_ended.is_none())
+            .count();
+        self.ports.set(inner.jobs.len() as i64);
+        self.active_ports.set(active as i64);
+    }
+
+    fn state_time(&self, previous: Option<(DateTime<Utc>, String)>, at: &DateTime<Utc>) {
+        if let Some((since, previous)) = previous {
+            let seconds = (*at - since).num_milliseconds().max(0) as f64 / 1000.0;
+            self.state_seconds
+                .get_or_create(&StateLabels { state: previous.as_str().into() })
+                .inc_by(seconds);
+        }
+    }
+
+    fn job_finished(&self, inner: &MetricsInner, port: &str) {
+        let job = &inner.jobs[port];
+        let outcome = match job.get_max_information_type(&inner.severity_policy) {
+            DeviceInformationType::Info => "success",
+            DeviceInformationType::Warning => "warning",
+            DeviceInformationType::Error => "error",
+        };
+        let mut vendor = "unknown";
+        let mut model = "unknown";
+        for item in job.info_items.iter() {
+            match item {
+                DeviceInformation::Vendor(v) => vendor = v,
+                DeviceInformation::Model(m) => model = m,
+                _ => {}
+            }
+        }
+        self.jobs_finished
+            .get_or_create(&OutcomeLabels {
+                outcome: outcome.into(),
+                vendor: vendor.into(),
+                model: model.into(),
+            })
+            .inc();
+    }
+
+    pub fn serial_data(&self, port: &str, len: usize) {
+        self.serial_bytes
+            .get_or_create(&PortLabels { port: port.into() })
+            .inc_by(len as u64);
+    }
+
+    /// A broadcast receiver fell behind and skipped `skipped` messages.
+    pub fn lagged(&self, consumer: &str, skipped: u64) {
+        self.lagged
+            .get_or_create(&ConsumerLabels { consumer: consumer.into() })
+            .inc_by(skipped);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        // Writing to a String does not fail.
+        let _ = prometheus_client::encoding::text::encode(&mut out, &self.registry);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    #[test]
+    fn job_metrics() {
+        let metrics = Metrics::new(SeverityPolicy::default());
+        let start = Utc::now();
+        metrics.job_update("S1", &JobUpdate::JobStart(start));
+        metrics.job_update("S1", &JobUpdate::JobStageTransition(start, "Init".to_string()));
+        metrics.job_update(
+            "S1",
+            &JobUpdate::JobStageTransition(start + TimeDelta::milliseconds(1500), "Wipe".to_string()),
+        );
+        metrics.job_update("S1", &JobUpdate::JobNewInfoItem(DeviceInformation::Vendor("Ju\"niper".to_string())));
+        metrics.serial_data("S1", 42);
+        assert!(metrics.render().contains("cthulhu_active_ports 1\n"));
+
+        metrics.job_update("S1", &JobUpdate::JobNewInfoItem(DeviceInformation::LoopDetected));
+        metrics.job_update("S1", &JobUpdate::JobEnd(start + TimeDelta::seconds(3)));
+        // Ending a job that already ended counts nothing.
+        metrics.job_update("S1", &JobUpdate::JobEnd(start + TimeDelta::seconds(5)));
+        let out = metrics.render();
+        assert!(out.contains("cthulhu_active_ports 0\n"));
+        assert!(out.contains("cthulhu_state_seconds_total{state=\"Init\"} 1.5\n"));
+        // The last state is counted when the job ends.
+        assert!(out.contains("cthulhu_state_seconds_total{state=\"Wipe\"} 1.5\n"));
+        assert!(out.contains("cthulhu_loop_detections_total{port=\"S1\"} 1\n"));
+        assert!(out.contains("cthulhu_serial_bytes_total{port=\"S1\"} 42\n"));
+        assert!(out.contains(
+            "cthulhu_jobs_finished_total{outcome=\"error\",vendor=\"Ju\\\"niper\",model=\"unknown\"} 1\n"
+        ));
+        assert!(out.ends_with("# EOF\n"));
+    }
+}
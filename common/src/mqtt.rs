@@ -0,0 +1,172 @@
+use color_eyre::eyre::eyre;
+use rumqttc::{MqttOptions, Transport};
+use serde::Deserialize;
+use std::fmt::{Debug, Formatter};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Credentials and TLS settings for the connection to the MQTT broker.
+///
+/// Flattened into the MQTT section of every config, so all of these sit next to `host` and `port`.
+#[derive(Deserialize, Clone, Default)]
+pub struct MQTTSecurityConfig {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// PEM file with the CA the broker certificate has to be signed by, setting it enables TLS.
+    pub ca_file: Option<PathBuf>,
+    /// PEM client certificate, for brokers that authenticate clients by certificate.
+    pub client_cert_file: Option<PathBuf>,
+    pub client_key_file: Option<PathBuf>,
+}
+
+impl Debug for MQTTSecurityConfig {
+    // Configs get logged on startup, keep the password out of the logs.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MQTTSecurityConfig")
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("ca_file", &self.ca_file)
+            .field("client_cert_file", &self.client_cert_file)
+            .field("client_key_file", &self.client_key_file)
+            .finish()
+    }
+}
+
+/// Build the options for an MQTT client, shared by everything that talks to heaven.
+pub async fn mqtt_options(
+    id: &str,
+    host: &str,
+    port: u16,
+    security: &MQTTSecurityConfig,
+) -> color_eyre::Result<MqttOptions> {
+    let mut mqttoptions = MqttOptions::new(id, host, port);
+    mqttoptions.set_keep_alive(Duration::from_secs(5));
+
+    match (security.username.as_ref(), security.password.as_ref()) {
+        (Some(username), password) => {
+            mqttoptions.set_credentials(username, password.cloned().unwrap_or_default());
+        }
+        (None, Some(_)) => return Err(eyre!("MQTT password is set without a username")),
+        (None, None) => {}
+    }
+
+    let client_auth = match (
+        security.client_cert_file.as_ref(),
+        security.client_key_file.as_ref(),
+    ) {
+        (Some(cert), Some(key)) => Some((tokio::fs::read(cert).await?, tokio::fs::read(key).await?)),
+        (None, None) => None,
+        _ => return Err(eyre!("MQTT client certificate and key have to be set together")),
+    };
+    match (security.ca_file.as_ref(), client_auth) {
+        (Some(ca_file), client_auth) => {
+            // Only the configured CA is trusted, not the system store.
+            let ca = tokio::fs::read(ca_file).await?;
+            mqttoptions.set_transport(Transport::tls(ca, client_auth, None));
+        }
+        (None, Some(_)) => return Err(eyre!("MQTT client certificates need a ca_file to enable TLS")),
+        (None, None) => {}
+    }
+    Ok(mqttoptions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+    use rumqttc::tokio_rustls::TlsAcceptor;
+    use rumqttc::tokio_rustls::rustls::pki_types::pem::PemObject;
+    use rumqttc::tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use rumqttc::tokio_rustls::rustls::server::WebPkiClientVerifier;
+    use rumqttc::tokio_rustls::rustls::{RootCertStore, ServerConfig};
+    use rumqttc::{AsyncClient, ConnAck, ConnectReturnCode, ConnectionError, Event, Packet};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const CA: &[u8] = include_bytes!("../testdata/ca.pem");
+    const SERVER_CERT: &[u8] = include_bytes!("../testdata/server.pem");
+    const SERVER_KEY: &[u8] = include_bytes!("../testdata/server.key");
+
+    fn testdata(file: &str) -> Option<PathBuf> {
+        Some(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("testdata").join(file))
+    }
+
+    /// Just enough of a broker to answer a single CONNECT, requires a client certificate signed by the test CA.
+    async fn serve() -> u16 {
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_slice(CA).unwrap()).unwrap();
+        let config = ServerConfig::builder()
+            .with_client_cert_verifier(WebPkiClientVerifier::builder(Arc::new(roots)).build().unwrap())
+            .with_single_cert(
+                vec![CertificateDer::from_pem_slice(SERVER_CERT).unwrap()],
+                PrivateKeyDer::from_pem_slice(SERVER_KEY).unwrap(),
+            )
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let Ok(mut stream) = acceptor.accept(socket).await else {
+                return;
+            };
+            let mut buf = BytesMut::new();
+            let connect = loop {
+                match rumqttc::read(&mut buf, 4096) {
+                    Ok(Packet::Connect(connect)) => break connect,
+                    Ok(p) => panic!("Expected CONNECT, got {p:?}"),
+                    Err(_) => {
+                        if stream.read_buf(&mut buf).await.unwrap() == 0 {
+                            return;
+                        }
+                    }
+                }
+            };
+            let code = match connect.login {
+                Some(login) if login.username == "S1" && login.password == "hunter2" => ConnectReturnCode::Success,
+                _ => ConnectReturnCode::BadUserNamePassword,
+            };
+            let mut out = BytesMut::new();
+            ConnAck::new(code, false).write(&mut out).unwrap();
+            stream.write_all(&out).await.unwrap();
+            // Keep the connection up until the client is done with it.
+            let _ = stream.read_buf(&mut buf).await;
+        });
+        port
+    }
+
+    fn security(password: &str, ca: &str) -> MQTTSecurityConfig {
+        MQTTSecurityConfig {
+            username: Some("S1".to_string()),
+            password: Some(password.to_string()),
+            ca_file: testdata(ca),
+            client_cert_file: testdata("client.pem"),
+            client_key_file: testdata("client.key"),
+        }
+    }
+
+    async fn connect(security: &MQTTSecurityConfig) -> Result<Event, ConnectionError> {
+        let port = serve().await;
+        let options = mqtt_options("S1", "localhost", port, security).await.unwrap();
+        let (_client, mut eventloop) = AsyncClient::new(options, 10);
+        eventloop.poll().await
+    }
+
+    #[tokio::test]
+    async fn tls_broker() {
+        let event = connect(&security("hunter2", "ca.pem")).await.unwrap();
+        assert!(matches!(event, Event::Incoming(Packet::ConnAck(ack)) if ack.code == ConnectReturnCode::Success));
+
+        assert!(matches!(
+            connect(&security("wrong", "ca.pem")).await,
+            Err(ConnectionError::ConnectionRefused(ConnectReturnCode::BadUserNamePassword))
+        ));
+
+        // A broker certificate from another CA is refused.
+        assert!(matches!(
+            connect(&security("hunter2", "client.pem")).await,
+            Err(ConnectionError::Tls(_))
+        ));
+    }
+}
@@ -0,0 +1,74 @@
+use color_eyre::eyre::eyre;
+
+const FLAG_DEFLATE: u8 = 1;
+/// Sequence number and flags.
+const HEADER_LEN: usize = 9;
+/// Refuse to inflate a batch past this, a batch is only a few KiB of console output.
+const MAX_INFLATED_LEN: usize = 16 * 1024 * 1024;
+
+/// A batch of serial output, as published on `cthulhu/<id>/serial`.
+///
+/// On the wire this is the big endian sequence number, a flags byte and the (optionally deflated) data.
+/// Sequence numbers count up from 0 every time the port starts, so a jump means batches were lost.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerialBatch {
+    pub seq: u64,
+    pub data: Vec<u8>,
+}
+
+impl SerialBatch {
+    pub fn encode(&self, compress: bool) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN + self.data.len());
+        out.extend(self.seq.to_be_bytes());
+        let compressed = compress.then(|| miniz_oxide::deflate::compress_to_vec(&self.data, 6));
+        match compressed {
+            // Short batches tend to grow when deflated.
+            Some(compressed) if compressed.len() < self.data.len() => {
+                out.push(FLAG_DEFLATE);
+                out.extend(compressed);
+            }
+            _ => {
+                out.push(0);
+                out.extend(&self.data);
+            }
+        }
+        out
+    }
+
+    pub fn decode(payload: &[u8]) -> color_eyre::Result<Self> {
+        if payload.len() < HEADER_LEN {
+            return Err(eyre!("Serial batch is too short"));
+        }
+        let (header, data) = payload.split_at(HEADER_LEN);
+        let seq = u64::from_be_bytes(header[..8].try_into()?);
+        let data = match header[8] {
+            0 => data.to_vec(),
+            FLAG_DEFLATE => miniz_oxide::inflate::decompress_to_vec_with_limit(data, MAX_INFLATED_LEN)
+                .map_err(|e| eyre!("Serial batch {seq} does not inflate: {e}"))?,
+            flags => return Err(eyre!("Serial batch {seq} has unknown flags {flags:#x}")),
+        };
+        Ok(SerialBatch { seq, data })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let batch = SerialBatch {
+            seq: 42,
+            data: b"Booting [/kernel]...\r\n".repeat(20),
+        };
+        let compressed = batch.encode(true);
+        assert!(compressed.len() < batch.data.len());
+        assert_eq!(SerialBatch::decode(&compressed).unwrap(), batch);
+        assert_eq!(SerialBatch::decode(&batch.encode(false)).unwrap(), batch);
+
+        // Not worth compressing, goes out as is.
+        let short = SerialBatch { seq: 0, data: b"login:".to_vec() };
+        assert_eq!(short.encode(true)[HEADER_LEN - 1], 0);
+        assert!(SerialBatch::decode(b"short").is_err());
+    }
+}
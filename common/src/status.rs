@@ -1,6 +1,8 @@
+use crate::certificate::SignedErasureCertificate;
 use crate::devinfo::DeviceInformation;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
 use crate::job::JobData;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -10,11 +12,110 @@ pub enum JobUpdate {
     JobEnd(DateTime<Utc>),
     JobNewInfoItem(DeviceInformation),
     JobFullData(JobData),
+    JobCertificate(Box<SignedErasureCertificate>),
+    PortDisconnected(DateTime<Utc>),
+    PortReconnected(DateTime<Utc>),
+    AngelStatus(AngelStatus),
+    PortTakenOver(Takeover),
+    PortReleased(DateTime<Utc>),
+    OperatorAction(DateTime<Utc>, OperatorAction),
+    /// The state files the port runs, and the ones it switches to once the current job is done.
+    ActiveStates { current: Vec<String>, next: Option<Vec<String>> },
+    ActiveStatesRejected(Box<RejectedActiveStates>),
+}
+
+/// A switch of state files the angel refused, e.g. because of an unknown id.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RejectedActiveStates {
+    pub states: Vec<String>,
+    pub reason: String,
+}
+
+/// Manual control over the state machine, recorded in the state history.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OperatorAction {
+    Pause,
+    Resume,
+    Step,
+    GotoState(String),
+}
+
+impl Display for OperatorAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OperatorAction::Pause => write!(f, "Pause"),
+            OperatorAction::Resume => write!(f, "Resume"),
+            OperatorAction::Step => write!(f, "Step"),
+            OperatorAction::GotoState(s) => write!(f, "Go to {s}"),
+        }
+    }
+}
+
+/// An operator is driving the port by hand from heaven, the state machine is paused.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Takeover {
+    pub since: DateTime<Utc>,
+    /// Heaven session that claimed the port, only its keystrokes reach the device.
+    #[serde(default)]
+    pub session: String,
+    /// States the job can be resumed in once the port is released.
+    pub states: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum JobCommand {
     ResetJob,
+    /// Same as [`JobCommand::RestartWhenIdle`], kept for older heavens.
     RestartAngel,
     GetJobData,
+    /// Finish the running jobs, then stop taking new ones.
+    Drain,
+    /// Finish the running jobs, then exit so the angel gets restarted.
+    RestartWhenIdle,
+    /// Checkpoint the running jobs and exit right away.
+    RestartNow,
+    /// Pause the job and pass keystrokes from `cthulhu/<id>/input` to the serial port.
+    Takeover { session: String },
+    /// Hand the port back to the job, optionally continuing in another state.
+    Release { state: Option<String> },
+    /// Stop taking transitions until resumed.
+    Pause,
+    Resume,
+    /// Take a single transition while paused.
+    Step,
+    /// Jump to a state without running any transition actions.
+    GotoState(String),
+    /// Rebuild the state machine from these state files, e.g. `["wipe", "provision"]`.
+    /// An idle port switches right away, a busy one once its job is done.
+    SetActiveStates(Vec<String>),
+}
+
+/// Whether the angel serving a port takes new jobs.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum AngelStatus {
+    #[default]
+    Running,
+    /// Finishing the current job, no new jobs are started.
+    Draining,
+    /// The angel is exiting.
+    GoingDown,
+    /// The angel has disappeared without shutting down, only ever set by heaven from its last will.
+    Lost,
+}
+
+/// Whether an angel is connected to the broker.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Presence {
+    Online,
+    /// Disconnected after a clean shutdown.
+    Offline,
+    /// Published by the broker as the angel's last will.
+    Lost,
+}
+
+/// Retained on `cthulhu/<angel id>/presence`, lists the ports so heaven knows which ones are affected.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AngelPresence {
+    pub presence: Presence,
+    pub ports: Vec<String>,
 }
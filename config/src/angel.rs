@@ -1,4 +1,7 @@
-use std::collections::BTreeMap;
+use color_eyre::eyre::eyre;
+use std::collections::{BTreeMap, BTreeSet};
+use cthulhu_common::devinfo::SeverityPolicy;
+use cthulhu_common::mqtt::MQTTSecurityConfig;
 use serde::Deserialize;
 use std::path::PathBuf;
 use crate::LoadableConfig;
@@ -7,14 +10,30 @@ use crate::LoadableConfig;
 pub struct AngelConfig {
     pub log_level: Option<String>,
     pub log_dir: Option<PathBuf>,
+    /// File containing the hex encoded ed25519 secret key used to sign erasure certificates.
+    pub certificate_key: Option<PathBuf>,
     #[serde(default = "default_active_states")]
     pub active_states: Vec<String>,
+    /// Pick up an interrupted job where it left off after a restart, instead of starting over.
+    #[serde(default)]
+    pub resume_jobs: bool,
+    /// Serve Prometheus metrics on `/metrics` at this address, e.g. `0.0.0.0:9100`.
+    pub metrics_listen_address: Option<String>,
 
     #[serde(rename = "JobConfig", default)]
     pub job_config: BTreeMap<String, String>,
+    #[serde(rename = "SeverityPolicy", default)]
+    pub severity_policy: SeverityPolicy,
+    #[serde(rename = "LogRetention", default)]
+    pub log_retention: LogRetentionConfig,
+    #[serde(rename = "Watchdog", default)]
+    pub watchdog: WatchdogConfig,
 
+    /// Single port shorthand, the port is labelled with the heaven id.
     #[serde(flatten)]
-    pub port: AngelPortConfig,
+    pub port: Option<AngelPortConfig>,
+    #[serde(rename = "Port", default)]
+    pub ports: Vec<AngelPortEntry>,
     #[serde(rename = "Heaven")]
     pub heaven: Option<AngelHeavenConfig>,
 }
@@ -25,17 +44,174 @@ fn default_active_states() -> Vec<String> {
 
 impl LoadableConfig for AngelConfig {}
 
+impl AngelConfig {
+    /// All the ports served by this angel.
+    pub fn get_ports(&self) -> color_eyre::Result<Vec<AngelPortEntry>> {
+        let mut ports = self.ports.clone();
+        if let Some(port) = self.port.as_ref() {
+            ports.push(AngelPortEntry {
+                id: self.heaven.as_ref().map(|h| h.id.clone()).unwrap_or_default(),
+                port: port.clone(),
+                active_states: None,
+                job_config: BTreeMap::new(),
+            });
+        }
+        if ports.is_empty() {
+            return Err(eyre!("No ports configured"));
+        }
+        let mut ids = BTreeSet::new();
+        for p in ports.iter() {
+            if !ids.insert(p.id.as_str()) {
+                return Err(eyre!("Port id {:?} is used more than once", p.id));
+            }
+        }
+        Ok(ports)
+    }
+
+    pub fn get_active_states<'a>(&'a self, port: &'a AngelPortEntry) -> &'a Vec<String> {
+        port.active_states.as_ref().unwrap_or(&self.active_states)
+    }
+
+    pub fn get_job_config(&self, port: &AngelPortEntry) -> BTreeMap<String, String> {
+        let mut job_config = self.job_config.clone();
+        job_config.extend(port.job_config.clone());
+        job_config
+    }
+}
+
+/// One serial port served by this angel.
+#[derive(Deserialize, Debug, Clone)]
+pub struct AngelPortEntry {
+    pub id: String,
+    #[serde(flatten)]
+    pub port: AngelPortConfig,
+    /// Defaults to the top-level `active_states`.
+    pub active_states: Option<Vec<String>>,
+    /// Merged over the top-level `JobConfig`.
+    #[serde(rename = "JobConfig", default)]
+    pub job_config: BTreeMap<String, String>,
+}
+
+/// What happens to the logs of finished jobs in `log_dir`.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct LogRetentionConfig {
+    /// Delete the logs of jobs that ended more than this many days ago.
+    pub max_age_days: Option<u64>,
+    /// Delete the logs of the oldest jobs once all of them together take up more than this.
+    pub max_total_size_mb: Option<u64>,
+    /// Gzip the logs of a job once it has finished.
+    #[serde(default)]
+    pub compress: bool,
+}
+
+/// Ends jobs that are stuck, instead of leaving the port hanging.
+#[derive(Deserialize, Debug, Clone)]
+pub struct WatchdogConfig {
+    /// Longest a job may run, from start to finish.
+    pub job_timeout_secs: Option<u64>,
+    /// Longest a job may go without a transition.
+    pub state_timeout_secs: Option<u64>,
+    /// Overrides `state_timeout_secs` for slow states, keyed by state name.
+    #[serde(rename = "StateTimeouts", default)]
+    pub state_timeouts: BTreeMap<String, u64>,
+    /// Where a timed out job goes, entered without running any transition actions.
+    #[serde(default = "default_timeout_state")]
+    pub timeout_state: String,
+}
+
+fn default_timeout_state() -> String {
+    "EndJob".to_string()
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig {
+            job_timeout_secs: None,
+            state_timeout_secs: None,
+            state_timeouts: BTreeMap::new(),
+            timeout_state: default_timeout_state(),
+        }
+    }
+}
+
+impl WatchdogConfig {
+    /// How long a job may stay in `state`, if there is a limit.
+    pub fn state_timeout(&self, state: &str) -> Option<u64> {
+        self.state_timeouts.get(state).copied().or(self.state_timeout_secs)
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct AngelHeavenConfig {
     pub id: String,
     pub host: String,
     pub port: u16,
+    #[serde(flatten)]
+    pub security: MQTTSecurityConfig,
+    #[serde(rename = "SerialStream", default)]
+    pub serial_stream: SerialStreamConfig,
+}
+
+/// How the serial output is streamed to heaven.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SerialStreamConfig {
+    /// Output is held back for at most this long, so that it goes out in a few larger messages.
+    #[serde(default = "default_batch_interval_ms")]
+    pub batch_interval_ms: u64,
+    /// A batch is sent early once it has grown to this many bytes.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// Deflate the batches, trades some CPU for broker bandwidth.
+    #[serde(default)]
+    pub compress: bool,
+}
+
+impl Default for SerialStreamConfig {
+    fn default() -> Self {
+        SerialStreamConfig {
+            batch_interval_ms: default_batch_interval_ms(),
+            batch_size: default_batch_size(),
+            compress: false,
+        }
+    }
+}
+
+fn default_batch_interval_ms() -> u64 {
+    100
+}
+
+fn default_batch_size() -> usize {
+    16 * 1024
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub enum AngelPortConfig {
     TTY(TTYConfig),
     RawTCP(RawTCPConfig),
+    RFC2217(RFC2217Config),
+    SSH(SSHConfig),
+}
+
+impl AngelPortConfig {
+    /// The baud rate to start detection at, if this port has autobaud enabled.
+    pub fn autobaud(&self) -> Option<TTYBaudrate> {
+        match self {
+            AngelPortConfig::TTY(c) if c.autobaud => Some(c.baudrate),
+            AngelPortConfig::RFC2217(c) if c.autobaud => Some(c.baudrate),
+            _ => None,
+        }
+    }
+
+    /// The same port at another baud rate, ports without a baud rate are returned as is.
+    pub fn with_baudrate(&self, baudrate: TTYBaudrate) -> Self {
+        let mut config = self.clone();
+        match &mut config {
+            AngelPortConfig::TTY(c) => c.baudrate = baudrate,
+            AngelPortConfig::RFC2217(c) => c.baudrate = baudrate,
+            AngelPortConfig::RawTCP(_) | AngelPortConfig::SSH(_) => {}
+        }
+        config
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -43,6 +219,17 @@ pub struct TTYConfig {
     pub path: PathBuf,
     #[serde(default)]
     pub baudrate: TTYBaudrate,
+    #[serde(default = "default_data_bits")]
+    pub data_bits: u8,
+    #[serde(default)]
+    pub parity: SerialParity,
+    #[serde(default)]
+    pub stop_bits: SerialStopBits,
+    #[serde(default)]
+    pub flow_control: SerialFlowControl,
+    /// Start at `baudrate`, but cycle through the common rates while the data looks like garbage.
+    #[serde(default)]
+    pub autobaud: bool,
 }
 
 #[derive(Deserialize, Debug, Clone, Copy, Ord, PartialOrd, PartialEq, Eq)]
@@ -58,3 +245,69 @@ impl Default for TTYBaudrate {
 pub struct RawTCPConfig {
     pub endpoint: String,
 }
+
+/// Telnet connection to a console server port with RFC 2217 COM port control.
+#[derive(Deserialize, Debug, Clone)]
+pub struct RFC2217Config {
+    pub endpoint: String,
+    #[serde(default)]
+    pub baudrate: TTYBaudrate,
+    #[serde(default = "default_data_bits")]
+    pub data_bits: u8,
+    #[serde(default)]
+    pub parity: SerialParity,
+    #[serde(default)]
+    pub stop_bits: SerialStopBits,
+    #[serde(default)]
+    pub flow_control: SerialFlowControl,
+    /// Start at `baudrate`, but cycle through the common rates while the data looks like garbage.
+    #[serde(default)]
+    pub autobaud: bool,
+}
+
+/// SSH session to a console server port, e.g. `admin:port12` on an Opengear.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SSHConfig {
+    pub host: String,
+    #[serde(default = "default_ssh_port")]
+    pub port: u16,
+    pub username: String,
+    pub password: Option<String>,
+    pub key_file: Option<PathBuf>,
+    /// SHA256 fingerprint of the host key as printed by `ssh-keygen -l`, any key is accepted when unset.
+    pub host_key: Option<String>,
+}
+
+fn default_ssh_port() -> u16 {
+    22
+}
+
+fn default_data_bits() -> u8 {
+    8
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SerialParity {
+    #[default]
+    None,
+    Odd,
+    Even,
+    Mark,
+    Space,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SerialStopBits {
+    #[default]
+    One,
+    Two,
+    OnePointFive,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SerialFlowControl {
+    #[default]
+    None,
+    XonXoff,
+    Hardware,
+}
@@ -1,14 +1,23 @@
+use cthulhu_common::devinfo::SeverityPolicy;
+use cthulhu_common::mqtt::MQTTSecurityConfig;
 use serde::Deserialize;
+use std::path::PathBuf;
 use crate::LoadableConfig;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct HeavenConfig {
     pub log_level: Option<String>,
+    /// Where to keep the erasure certificates received from the angels.
+    pub certificate_dir: Option<PathBuf>,
+    /// Where to keep the serial recordings of the jobs, for replaying them from the port page.
+    pub recording_dir: Option<PathBuf>,
 
     #[serde(rename = "Web")]
     pub web: HeavenWebConfig,
     #[serde(rename = "MQTT")]
     pub mqtt: HeavenMQTTConfig,
+    #[serde(rename = "SeverityPolicy", default)]
+    pub severity_policy: SeverityPolicy,
 }
 
 impl LoadableConfig for HeavenConfig {}
@@ -22,4 +31,6 @@ pub struct HeavenMQTTConfig {
     pub id: Option<String>,
     pub host: String,
     pub port: u16,
+    #[serde(flatten)]
+    pub security: MQTTSecurityConfig,
 }
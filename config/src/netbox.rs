@@ -1,4 +1,6 @@
 use crate::LoadableConfig;
+use cthulhu_common::devinfo::SeverityPolicy;
+use cthulhu_common::mqtt::MQTTSecurityConfig;
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug, Clone)]
@@ -8,6 +10,8 @@ pub struct NetboxConfig {
 
     #[serde(rename = "Heaven")]
     pub heaven: NetboxHeavenConfig,
+    #[serde(rename = "SeverityPolicy", default)]
+    pub severity_policy: SeverityPolicy,
 }
 impl LoadableConfig for NetboxConfig {}
 
@@ -16,6 +20,8 @@ pub struct NetboxHeavenConfig {
     pub id: String,
     pub host: String,
     pub port: u16,
+    #[serde(flatten)]
+    pub security: MQTTSecurityConfig,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -1,4 +1,6 @@
 use crate::LoadableConfig;
+use cthulhu_common::devinfo::SeverityPolicy;
+use cthulhu_common::mqtt::MQTTSecurityConfig;
 use serde::Deserialize;
 use std::collections::BTreeMap;
 
@@ -10,6 +12,8 @@ pub struct OcthulhuConfig {
     pub network_serials: Vec<OcthulhuNetworkSerial>,
     #[serde(rename = "PortMapping", default)]
     pub port_mapping: BTreeMap<String, Vec<String>>,
+    #[serde(rename = "SeverityPolicy", default)]
+    pub severity_policy: SeverityPolicy,
 }
 
 impl LoadableConfig for OcthulhuConfig {}
@@ -19,6 +23,8 @@ pub struct OcthulhuHeavenConfig {
     pub id: String,
     pub host: String,
     pub port: u16,
+    #[serde(flatten)]
+    pub security: MQTTSecurityConfig,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -1,14 +1,14 @@
 use crate::args::Cli;
 use crate::client::{NetboxClient, NetboxJournalEntryKind};
 use clap::Parser;
-use cthulhu_common::devinfo::{DeviceInformation, DeviceInformationType};
+use cthulhu_common::devinfo::{DeviceInformation, DeviceInformationType, SeverityPolicy};
 use cthulhu_common::job::JobData;
+use cthulhu_common::mqtt::mqtt_options;
 use cthulhu_common::status::{JobCommand, JobUpdate};
 use cthulhu_config::netbox::{NetboxConfig, NetboxHeavenConfig, NetboxNBConfig};
 use regex::Regex;
 use rumqttc::{Event, Incoming, MqttOptions, QoS};
 use std::collections::BTreeMap;
-use std::time::Duration;
 use tracing::{info, warn};
 use cthulhu_config::LoadableConfig;
 
@@ -62,7 +62,7 @@ async fn main() -> color_eyre::Result<()> {
                             "Device with serial number {} on port {} has finished!",
                             sn, label
                         );
-                        if let Err(e) = update_device(&nb_client, &config.netbox, &sn, data).await {
+                        if let Err(e) = update_device(&nb_client, &config.netbox, &config.severity_policy, &sn, data).await {
                             warn!("Unable to update device with ID {}: {}", sn, e);
                         }
                     }
@@ -76,12 +76,13 @@ async fn main() -> color_eyre::Result<()> {
 async fn update_device(
     nb_client: &NetboxClient,
     nb_config: &NetboxNBConfig,
+    policy: &SeverityPolicy,
     sn: &str,
     data: &JobData,
 ) -> color_eyre::Result<()> {
     let device_id = nb_client.get_device_id_by_serial(&sn).await?;
 
-    if data.get_max_information_type() != DeviceInformationType::Error {
+    if data.get_max_information_type(policy) != DeviceInformationType::Error {
         nb_client
             .set_device_status(device_id, &nb_config.target_status)
             .await?;
@@ -93,7 +94,7 @@ async fn update_device(
     comment.push_str("## Job Information\n");
     comment.push_str("| Key | Value |\n");
     comment.push_str("| --- | ----- |\n");
-    comment.push_str(&format!("| Max level | {:?} |\n", data.get_max_information_type()));
+    comment.push_str(&format!("| Max level | {:?} |\n", data.get_max_information_type(policy)));
     comment.push_str(&format!("| Serial Port | {} |\n", data.label));
     if let Some(job_started) = data.job_started.as_ref() {
         comment.push_str(&format!("| Start Time | {} |\n", job_started));
@@ -117,10 +118,44 @@ async fn update_device(
     comment.push_str("## Device Information Items\n");
     comment.push_str("| Level | Item |\n");
     comment.push_str("| ----- | ---- |\n");
-    for item in data.info_items.iter() {
-        comment.push_str(&format!("| {:?} | {:?} |\n", item.get_type(), item));
+    for item in data.info_items.iter().filter(|i| !i.is_inventory()) {
+        comment.push_str(&format!("| {:?} | {} |\n", policy.get_type(item), item));
     }
     comment.push_str("\n\n");
+    if data.info_items.iter().any(|i| i.is_inventory()) {
+        comment.push_str("## Hardware Inventory\n");
+        for item in data.info_items.iter() {
+            match item {
+                DeviceInformation::PortCount(n) => comment.push_str(&format!("Ports: {}\n\n", n)),
+                DeviceInformation::PowerSupplies(v) | DeviceInformation::Fans(v) => {
+                    comment.push_str("| Component | Status |\n");
+                    comment.push_str("| --------- | ------ |\n");
+                    for c in v {
+                        comment.push_str(&format!("| {} | {} |\n", c.name, c.status));
+                    }
+                    comment.push('\n');
+                }
+                DeviceInformation::Transceivers(v) => {
+                    comment.push_str("| Port | Model | Serial |\n");
+                    comment.push_str("| ---- | ----- | ------ |\n");
+                    for t in v {
+                        comment.push_str(&format!("| {} | {} | {} |\n", t.port, t.model, t.serial));
+                    }
+                    comment.push('\n');
+                }
+                DeviceInformation::Licenses(v) => {
+                    comment.push_str("| License |\n");
+                    comment.push_str("| ------- |\n");
+                    for l in v {
+                        comment.push_str(&format!("| {} |\n", l));
+                    }
+                    comment.push('\n');
+                }
+                _ => {}
+            }
+        }
+        comment.push_str("\n\n");
+    }
     comment.push_str("## State history\n");
     comment.push_str("| Time | State |\n");
     comment.push_str("| ---- | ----- |\n");
@@ -128,7 +163,7 @@ async fn update_device(
         comment.push_str(&format!("| {} | {} |\n", time, state));
     }
 
-    let kind = match data.get_max_information_type() {
+    let kind = match data.get_max_information_type(policy) {
         DeviceInformationType::Info => NetboxJournalEntryKind::Success,
         DeviceInformationType::Warning => NetboxJournalEntryKind::Warning,
         DeviceInformationType::Error => NetboxJournalEntryKind::Danger,
@@ -148,7 +183,5 @@ fn get_sn_from_job(data: &JobData) -> Option<String> {
 }
 
 async fn mqtt_options_from_config(config: &NetboxHeavenConfig) -> color_eyre::Result<MqttOptions> {
-    let mut mqttoptions = MqttOptions::new(&config.id, &config.host, config.port);
-    mqttoptions.set_keep_alive(Duration::from_secs(5));
-    Ok(mqttoptions)
+    mqtt_options(&config.id, &config.host, config.port, &config.security).await
 }
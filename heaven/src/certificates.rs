@@ -0,0 +1,73 @@
+use crate::file_name_safe;
+use cthulhu_common::certificate::SignedErasureCertificate;
+use std::path::PathBuf;
+use tracing::{info, warn};
+
+/// On-disk collection of erasure certificates, one JSON file per job.
+#[derive(Clone, Debug)]
+pub struct CertificateStore {
+    dir: Option<PathBuf>,
+}
+
+impl CertificateStore {
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        Self { dir }
+    }
+
+    pub async fn store(&self, certificate: &SignedErasureCertificate) -> color_eyre::Result<()> {
+        let Some(dir) = self.dir.as_ref() else {
+            return Ok(());
+        };
+        let c = &certificate.certificate;
+        let serial = file_name_safe(c.serial_number.as_deref().unwrap_or("unknown"));
+        let port = file_name_safe(&c.port_id);
+        let ended = c.job_ended.unwrap_or_default().format("%Y-%m-%d--%H:%M:%S");
+
+        let mut path = dir.clone();
+        path.push(format!("{serial}--{ended}--{port}.json"));
+        tokio::fs::create_dir_all(dir).await?;
+        tokio::fs::write(&path, serde_json::to_vec_pretty(certificate)?).await?;
+        info!("Stored erasure certificate {path:?}.");
+        Ok(())
+    }
+
+    /// All stored certificates, oldest first.
+    pub async fn list(&self) -> color_eyre::Result<Vec<SignedErasureCertificate>> {
+        let Some(dir) = self.dir.as_ref() else {
+            return Ok(Vec::new());
+        };
+        let mut certificates = Vec::new();
+        let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+            return Ok(certificates);
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.path().extension().is_none_or(|e| e != "json") {
+                continue;
+            }
+            let data = tokio::fs::read(entry.path()).await?;
+            match serde_json::from_slice::<SignedErasureCertificate>(&data) {
+                Ok(c) => certificates.push(c),
+                Err(e) => warn!("Skipping unreadable certificate {:?}: {e}", entry.path()),
+            }
+        }
+        certificates.sort_by_key(|c| c.certificate.job_ended);
+        Ok(certificates)
+    }
+
+    pub async fn for_serials(
+        &self,
+        serials: &[&str],
+    ) -> color_eyre::Result<Vec<SignedErasureCertificate>> {
+        Ok(self
+            .list()
+            .await?
+            .into_iter()
+            .filter(|c| {
+                c.certificate
+                    .serial_number
+                    .as_deref()
+                    .is_some_and(|s| serials.contains(&s))
+            })
+            .collect())
+    }
+}
@@ -1,7 +1,11 @@
 use crate::args::Cli;
+use crate::certificates::CertificateStore;
 use crate::manager::JobManager;
 use crate::mqtt::MQTTSender;
+use crate::recordings::RecordingStore;
 use clap::Parser;
+use cthulhu_common::metrics::Metrics;
+use cthulhu_common::mqtt::mqtt_options;
 use cthulhu_config::heaven::{HeavenConfig, HeavenMQTTConfig};
 use rumqttc::MqttOptions;
 use std::str::FromStr;
@@ -15,8 +19,11 @@ use tracing_subscriber::{Layer, Registry};
 use cthulhu_config::LoadableConfig;
 
 mod args;
+mod certificates;
 mod manager;
+mod metrics;
 mod mqtt;
+mod recordings;
 mod web;
 
 #[tokio::main]
@@ -39,7 +46,12 @@ async fn main() -> color_eyre::Result<()> {
     let mqtt_sender = MQTTSender::new(mqtt_client.clone())?;
     let mqtt_broadcast = mqtt::create_broadcast();
 
-    let manager = JobManager::new().await?;
+    let manager = JobManager::new(
+        CertificateStore::new(config.certificate_dir.clone()),
+        RecordingStore::new(config.recording_dir.clone()),
+    )
+    .await?;
+    let metrics = Metrics::new(config.severity_policy.clone());
 
     let a = yeller(
         "web".to_string(),
@@ -48,6 +60,7 @@ async fn main() -> color_eyre::Result<()> {
             manager.clone(),
             mqtt_sender.clone(),
             mqtt_broadcast.clone(),
+            metrics.clone(),
         ),
     )
     .await;
@@ -58,7 +71,12 @@ async fn main() -> color_eyre::Result<()> {
     .await;
     let c = yeller(
         "manager".to_string(),
-        manager::manager_main(mqtt_broadcast, mqtt_sender, manager),
+        manager::manager_main(mqtt_broadcast.clone(), manager, metrics.clone()),
+    )
+    .await;
+    let d = yeller(
+        "metrics".to_string(),
+        metrics::metrics_main(mqtt_broadcast, metrics),
     )
     .await;
 
@@ -75,6 +93,10 @@ async fn main() -> color_eyre::Result<()> {
             tokio::time::sleep(Duration::from_secs(1)).await;
             c??;
         }
+        d = d => {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            d??;
+        }
     }
 
     Ok(())
@@ -99,11 +121,13 @@ async fn yeller<T: Send + 'static, F: Future<Output = color_eyre::Result<T>> + S
 }
 
 async fn mqtt_options_from_config(config: &HeavenMQTTConfig) -> color_eyre::Result<MqttOptions> {
-    let mut mqttoptions = MqttOptions::new(
-        config.id.as_ref().unwrap_or(&"heaven".to_string()),
-        &config.host,
-        config.port,
-    );
-    mqttoptions.set_keep_alive(Duration::from_secs(5));
-    Ok(mqttoptions)
+    let id = config.id.as_deref().unwrap_or("heaven");
+    mqtt_options(id, &config.host, config.port, &config.security).await
+}
+
+/// `name` with everything but ASCII letters, digits and dashes replaced, so it stays one path component.
+fn file_name_safe(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect()
 }
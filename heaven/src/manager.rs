@@ -1,6 +1,10 @@
-use crate::mqtt::{BroadcastSender, MQTTBroadcast, MQTTSender};
-use cthulhu_common::status::{JobCommand, JobUpdate};
+use crate::certificates::CertificateStore;
+use crate::mqtt::{BroadcastSender, MQTTBroadcast};
+use crate::recordings::RecordingStore;
+use cthulhu_common::metrics::Metrics;
+use cthulhu_common::status::{AngelPresence, AngelStatus, JobUpdate, Presence};
 use serde::Serialize;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tokio::sync::broadcast::error::RecvError;
@@ -15,6 +19,8 @@ pub struct PortManagerEntry {
 
 struct JobManagerInner {
     ports: Vec<PortManagerEntry>,
+    /// Ports of lost angels, with the angel status they had before.
+    lost: BTreeMap<String, AngelStatus>,
 }
 
 impl JobManagerInner {
@@ -35,20 +41,43 @@ impl JobManagerInner {
             self.ports.last_mut().unwrap()
         }
     }
+
+    fn mark_lost(&mut self, port_label: &str) {
+        let existing = self.get_port_mut(port_label);
+        let status = std::mem::replace(&mut existing.data.angel_status, AngelStatus::Lost);
+        if status != AngelStatus::Lost {
+            self.lost.insert(port_label.to_string(), status);
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct JobManager {
     inner: Arc<RwLock<JobManagerInner>>,
+    certificates: CertificateStore,
+    recordings: RecordingStore,
 }
 
 impl JobManager {
-    pub async fn new() -> color_eyre::Result<Self> {
+    pub async fn new(certificates: CertificateStore, recordings: RecordingStore) -> color_eyre::Result<Self> {
         Ok(Self {
-            inner: Arc::new(RwLock::new(JobManagerInner { ports: Vec::new() })),
+            inner: Arc::new(RwLock::new(JobManagerInner {
+                ports: Vec::new(),
+                lost: BTreeMap::new(),
+            })),
+            certificates,
+            recordings,
         })
     }
 
+    pub fn certificates(&self) -> &CertificateStore {
+        &self.certificates
+    }
+
+    pub fn recordings(&self) -> &RecordingStore {
+        &self.recordings
+    }
+
     pub async fn get_ports(&self) -> Vec<PortManagerEntry> {
         let r = self.inner.read().await;
         r.ports
@@ -57,6 +86,16 @@ impl JobManager {
             .collect()
     }
 
+    /// Session that has taken over the port, if any.
+    pub async fn takeover_session(&self, label: &str) -> Option<String> {
+        let r = self.inner.read().await;
+        r.ports
+            .iter()
+            .find(|p| p.data.label == label)
+            .and_then(|p| p.data.takeover.as_ref())
+            .map(|t| t.session.clone())
+    }
+
     pub async fn get_port(&self, label: &str) -> Option<PortManagerEntry> {
         let r = self.inner.read().await;
         r.ports
@@ -71,7 +110,22 @@ impl JobManager {
         existing.log_buffer.extend(data);
         Ok(())
     }
+    /// Leave a visible mark in every log where skipped serial data might be missing.
+    async fn mark_gap(&self, skipped: u64) {
+        let marker = format!("\r\n[heaven fell behind and skipped {skipped} messages, output may be missing]\r\n");
+        let mut inner = self.inner.write().await;
+        for port in inner.ports.iter_mut() {
+            port.log_buffer.extend(marker.as_bytes());
+        }
+    }
+
     async fn accept_update(&self, port_label: &str, update: JobUpdate) -> color_eyre::Result<()> {
+        if let JobUpdate::JobCertificate(c) = &update
+            && let Err(e) = self.certificates.store(c).await
+        {
+            warn!("Failed to store erasure certificate: {e:?}");
+        }
+
         let mut inner = self.inner.write().await;
         let existing = inner.get_port_mut(port_label);
 
@@ -83,31 +137,60 @@ impl JobManager {
         }
 
         existing.data.update(update);
+        // Retained data can arrive after the last will, it must not bring the port back to life.
+        if inner.lost.contains_key(port_label) {
+            inner.mark_lost(port_label);
+        }
 
         Ok(())
     }
+
+    async fn accept_presence(&self, presence: AngelPresence) {
+        let mut inner = self.inner.write().await;
+        for label in presence.ports.iter() {
+            match presence.presence {
+                Presence::Lost => inner.mark_lost(label),
+                Presence::Online | Presence::Offline => {
+                    if let Some(status) = inner.lost.remove(label) {
+                        inner.get_port_mut(label).data.angel_status = status;
+                    }
+                }
+            }
+        }
+    }
 }
 
 pub async fn manager_main(
     broadcast: BroadcastSender,
-    sender: MQTTSender,
     manager: JobManager,
+    metrics: Metrics,
 ) -> color_eyre::Result<()> {
     let mut receiver = broadcast.subscribe();
 
-    sender.broadcast_command(JobCommand::GetJobData).await?;
-    
     loop {
         let msg = receiver.recv().await;
         match msg {
             Ok(MQTTBroadcast::JobUpdate { label, update }) => {
-                manager.accept_update(&label, update).await?;
+                manager.accept_update(&label, *update).await?;
             }
             Ok(MQTTBroadcast::SerialData { label, data }) => {
                 manager.append_log_data(&label, &data).await?;
             }
+            Ok(MQTTBroadcast::CastData { label, data }) => {
+                if let Err(e) = manager.recordings.append(&label, &data).await {
+                    warn!("Failed to store the recording of port {label}: {e:?}");
+                }
+            }
+            Ok(MQTTBroadcast::Presence { angel, presence }) => {
+                if presence.presence == Presence::Lost {
+                    warn!("Angel {angel} is gone, marking its ports {:?} as fatal.", presence.ports);
+                }
+                manager.accept_presence(presence).await;
+            }
             Err(RecvError::Lagged(n)) => {
                 warn!("Skipping {n} messages!");
+                metrics.lagged("manager", n);
+                manager.mark_gap(n).await;
             }
             Err(e) => {
                 return Err(e.into());
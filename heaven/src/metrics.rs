@@ -0,0 +1,28 @@
+use crate::mqtt::{BroadcastSender, MQTTBroadcast};
+use cthulhu_common::metrics::Metrics;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+/// Feed everything the angels publish into the metrics served on `/metrics`.
+pub async fn metrics_main(broadcast: BroadcastSender, metrics: Metrics) -> color_eyre::Result<()> {
+    let mut receiver = broadcast.subscribe();
+
+    loop {
+        match receiver.recv().await {
+            Ok(MQTTBroadcast::JobUpdate { label, update }) => {
+                metrics.job_update(&label, &update);
+            }
+            Ok(MQTTBroadcast::SerialData { label, data }) => {
+                metrics.serial_data(&label, data.len());
+            }
+            Ok(MQTTBroadcast::Presence { .. } | MQTTBroadcast::CastData { .. }) => {}
+            Err(RecvError::Lagged(n)) => {
+                warn!("Metrics skipped {n} messages!");
+                metrics.lagged("metrics", n);
+            }
+            Err(e) => {
+                return Err(e.into());
+            }
+        }
+    }
+}
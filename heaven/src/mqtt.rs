@@ -1,14 +1,21 @@
-use cthulhu_common::status::{JobCommand, JobUpdate};
+use cthulhu_common::asciicast;
+use cthulhu_common::job::JobData;
+use cthulhu_common::serial::SerialBatch;
+use cthulhu_common::status::{AngelPresence, JobCommand, JobUpdate};
 use regex::Regex;
 use rumqttc::{AsyncClient, Event, EventLoop, Incoming, QoS};
+use std::collections::BTreeMap;
 use tokio::sync::broadcast;
 use tokio::sync::broadcast::Sender;
-use tracing::{info, trace};
+use tracing::{info, trace, warn};
 
 #[derive(Clone, Debug)]
 pub enum MQTTBroadcast {
-    JobUpdate { label: String, update: JobUpdate },
+    JobUpdate { label: String, update: Box<JobUpdate> },
     SerialData { label: String, data: Vec<u8> },
+    /// Lines of the asciinema cast the angel records of the job.
+    CastData { label: String, data: Vec<u8> },
+    Presence { angel: String, presence: AngelPresence },
 }
 
 pub type BroadcastSender = Sender<MQTTBroadcast>;
@@ -24,9 +31,18 @@ pub async fn mqtt_main(
 ) -> color_eyre::Result<()> {
     mqtt_client.subscribe("cthulhu/+/update", QoS::AtLeastOnce).await?;
     mqtt_client.subscribe("cthulhu/+/serial", QoS::AtLeastOnce).await?;
+    mqtt_client.subscribe("cthulhu/+/cast", QoS::AtLeastOnce).await?;
+    // Both are retained, subscribing hands us the current state of every angel.
+    mqtt_client.subscribe("cthulhu/+/presence", QoS::AtLeastOnce).await?;
+    mqtt_client.subscribe("cthulhu/+/job", QoS::AtLeastOnce).await?;
 
     let update_re = Regex::new(r"cthulhu/(?<port_label>[^/]+)/update")?;
     let serial_re = Regex::new(r"cthulhu/(?<port_label>[^/]+)/serial")?;
+    let cast_re = Regex::new(r"cthulhu/(?<port_label>[^/]+)/cast")?;
+    let presence_re = Regex::new(r"cthulhu/(?<angel>[^/]+)/presence")?;
+    let job_re = Regex::new(r"cthulhu/(?<port_label>[^/]+)/job")?;
+    let mut next_seq: BTreeMap<String, u64> = BTreeMap::new();
+    let mut next_cast_seq: BTreeMap<String, u64> = BTreeMap::new();
     loop {
         let r = eventloop.poll().await?;
         match r {
@@ -35,13 +51,73 @@ pub async fn mqtt_main(
                     let label = (&caps["port_label"]).to_string();
                     let update: JobUpdate = serde_json::from_slice(&publish.payload)?;
                     info!("Received update for port {label}: {update:?}");
-                    let _ = sender.send(MQTTBroadcast::JobUpdate { label, update });
+                    let _ = sender.send(MQTTBroadcast::JobUpdate { label, update: Box::new(update) });
                 }
                 if let Some(caps) = serial_re.captures(&publish.topic) {
                     let label = (&caps["port_label"]).to_string();
-                    let data = publish.payload.to_vec();
+                    let batch = match SerialBatch::decode(&publish.payload) {
+                        Ok(batch) => batch,
+                        Err(e) => {
+                            warn!("Ignoring serial data for port {label}: {e}");
+                            continue;
+                        }
+                    };
+                    let mut data = Vec::new();
+                    // The numbering starts over whenever the port restarts, that is no loss.
+                    if let Some(&expected) = next_seq.get(&label)
+                        && batch.seq > expected
+                    {
+                        let lost = batch.seq - expected;
+                        warn!("Lost {lost} serial batches for port {label}.");
+                        data.extend(format!("\r\n\x1b[31m[{lost} serial log batches lost]\x1b[0m\r\n").as_bytes());
+                    }
+                    next_seq.insert(label.clone(), batch.seq + 1);
+                    data.extend(batch.data);
                     let _ = sender.send(MQTTBroadcast::SerialData { label, data });
                 }
+                if let Some(caps) = cast_re.captures(&publish.topic) {
+                    let label = caps["port_label"].to_string();
+                    let batch = match SerialBatch::decode(&publish.payload) {
+                        Ok(batch) => batch,
+                        Err(e) => {
+                            warn!("Ignoring recording data for port {label}: {e}");
+                            continue;
+                        }
+                    };
+                    let mut data = batch.data;
+                    if let Some(&expected) = next_cast_seq.get(&label)
+                        && batch.seq > expected
+                    {
+                        let lost = batch.seq - expected;
+                        warn!("Lost {lost} recording batches for port {label}.");
+                        data = mark_cast_gap(data, lost);
+                    }
+                    next_cast_seq.insert(label.clone(), batch.seq + 1);
+                    let _ = sender.send(MQTTBroadcast::CastData { label, data });
+                }
+                if let Some(caps) = presence_re.captures(&publish.topic) {
+                    let angel = caps["angel"].to_string();
+                    match serde_json::from_slice::<AngelPresence>(&publish.payload) {
+                        Ok(presence) => {
+                            info!("Angel {angel} is {:?}.", presence.presence);
+                            let _ = sender.send(MQTTBroadcast::Presence { angel, presence });
+                        }
+                        Err(e) => warn!("Ignoring malformed presence of angel {angel}: {e}"),
+                    }
+                }
+                // Live changes already arrive as updates, only the retained copy is news.
+                if let Some(caps) = job_re.captures(&publish.topic)
+                    && publish.retain
+                {
+                    let label = caps["port_label"].to_string();
+                    match serde_json::from_slice::<JobData>(&publish.payload) {
+                        Ok(data) => {
+                            let update = JobUpdate::JobFullData(data);
+                            let _ = sender.send(MQTTBroadcast::JobUpdate { label, update: Box::new(update) });
+                        }
+                        Err(e) => warn!("Ignoring malformed job data for port {label}: {e}"),
+                    }
+                }
             }
             _ => {
                 trace!("Ignoring unknown event.");
@@ -69,6 +145,13 @@ impl MQTTSender {
             .await?;
         Ok(())
     }
+    pub async fn send_input(&self, port: &str, data: Vec<u8>) -> color_eyre::Result<()> {
+        self.client
+            .publish(format!("cthulhu/{}/input", port), QoS::AtMostOnce, false, data)
+            .await?;
+        Ok(())
+    }
+
     pub async fn broadcast_command(&self, command: JobCommand) -> color_eyre::Result<()> {
         let data = serde_json::to_vec(&command)?;
         self.client
@@ -78,3 +161,39 @@ impl MQTTSender {
     }
 
 }
+
+/// Lost batches leave a hole in the recording, mark it where the recording picks up again.
+fn mark_cast_gap(data: Vec<u8>, lost: u64) -> Vec<u8> {
+    // A header starts the recording of a new job, the marker goes right after it.
+    let at = if data.starts_with(b"{") {
+        data.iter().position(|&b| b == b'\n').map_or(data.len(), |i| i + 1)
+    } else {
+        0
+    };
+    let time = data[at..]
+        .split(|&b| b == b'\n')
+        .next()
+        .and_then(|line| serde_json::from_slice::<(f64, String, String)>(line).ok())
+        .map_or(0.0, |(time, _, _)| time);
+    let mut marked = data[..at].to_vec();
+    marked.extend(asciicast::marker(time, &format!("gap: {lost} batches lost")).as_bytes());
+    marked.extend(&data[at..]);
+    marked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cast_gap() {
+        assert_eq!(
+            mark_cast_gap(b"[1.5,\"o\",\"x\"]\n".to_vec(), 2),
+            b"[1.5,\"m\",\"gap: 2 batches lost\"]\n[1.5,\"o\",\"x\"]\n"
+        );
+        assert_eq!(
+            mark_cast_gap(b"{\"version\":2}\n".to_vec(), 1),
+            b"{\"version\":2}\n[0.0,\"m\",\"gap: 1 batches lost\"]\n"
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use crate::file_name_safe;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// On-disk collection of the asciinema casts the angels record, one file per job.
+///
+/// Angels stream every line of their cast on `cthulhu/<id>/cast`, timed where the serial port is
+/// read rather than where heaven gets the batches. A header line starts the recording of a new job.
+#[derive(Clone, Debug)]
+pub struct RecordingStore {
+    dir: Option<PathBuf>,
+    /// The recording each port is currently appending to.
+    current: Arc<Mutex<BTreeMap<String, PathBuf>>>,
+}
+
+impl RecordingStore {
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        Self {
+            dir,
+            current: Arc::default(),
+        }
+    }
+
+    fn port_dir(&self, port_label: &str) -> Option<PathBuf> {
+        self.dir.as_ref().map(|d| d.join(file_name_safe(port_label)))
+    }
+
+    pub async fn append(&self, port_label: &str, data: &[u8]) -> color_eyre::Result<()> {
+        let Some(port_dir) = self.port_dir(port_label) else {
+            return Ok(());
+        };
+        let mut current = self.current.lock().await;
+        // A batch can hold the end of one job and the start of the next, events are arrays.
+        let mut rest = data;
+        while !rest.is_empty() {
+            if rest.starts_with(b"{") {
+                let name = format!("{}.cast", chrono::Utc::now().format("%Y-%m-%d--%H:%M:%S%.3f"));
+                let path = port_dir.join(name);
+                tokio::fs::create_dir_all(&port_dir).await?;
+                info!("Recording the job on port {port_label} to {path:?}.");
+                current.insert(port_label.to_string(), path);
+            }
+            let end = rest
+                .windows(2)
+                .position(|w| w == b"\n{")
+                .map(|i| i + 1)
+                .unwrap_or(rest.len());
+            let (lines, next) = rest.split_at(end);
+            rest = next;
+
+            // A resumed job carries on in its last recording, also when heaven restarted in between.
+            let path = match current.get(port_label) {
+                Some(path) => path.clone(),
+                None => match self.newest_on_disk(port_label).await? {
+                    Some(path) => {
+                        current.insert(port_label.to_string(), path.clone());
+                        path
+                    }
+                    None => {
+                        warn!("Dropping recording data for port {port_label}, its header was never received.");
+                        continue;
+                    }
+                },
+            };
+            let mut f = tokio::fs::OpenOptions::new().append(true).create(true).open(&path).await?;
+            f.write_all(lines).await?;
+        }
+        Ok(())
+    }
+
+    /// The recording of the current or else the most recent job on a port.
+    pub async fn latest(&self, port_label: &str) -> color_eyre::Result<Option<PathBuf>> {
+        if let Some(path) = self.current.lock().await.get(port_label) {
+            return Ok(Some(path.clone()));
+        }
+        self.newest_on_disk(port_label).await
+    }
+
+    async fn newest_on_disk(&self, port_label: &str) -> color_eyre::Result<Option<PathBuf>> {
+        let Some(port_dir) = self.port_dir(port_label) else {
+            return Ok(None);
+        };
+        let Ok(mut entries) = tokio::fs::read_dir(&port_dir).await else {
+            return Ok(None);
+        };
+        let mut latest = None;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            // The names start with the time the recording started.
+            if path.extension().is_some_and(|e| e == "cast") && latest.as_ref().is_none_or(|l| path > *l) {
+                latest = Some(path);
+            }
+        }
+        Ok(latest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn recordings_per_job() {
+        let dir = std::env::temp_dir().join(format!("cthulhu-recordings-{}", std::process::id()));
+        let store = RecordingStore::new(Some(dir.clone()));
+        store.append("S1", b"[0.5,\"o\",\"lost\"]\n").await.unwrap();
+        assert!(store.latest("S1").await.unwrap().is_none());
+
+        store.append("S1", b"{\"version\":2}\n[0.5,\"o\",\"login:\"]\n").await.unwrap();
+        let first = store.latest("S1").await.unwrap().unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        store.append("S1", b"[1,\"m\",\"EndJob\"]\n{\"version\":2}\n[0.1,\"o\",\"x\"]\n").await.unwrap();
+        let second = store.latest("S1").await.unwrap().unwrap();
+        assert_ne!(first, second);
+        assert_eq!(
+            tokio::fs::read_to_string(&first).await.unwrap(),
+            "{\"version\":2}\n[0.5,\"o\",\"login:\"]\n[1,\"m\",\"EndJob\"]\n"
+        );
+        assert_eq!(tokio::fs::read_to_string(&second).await.unwrap(), "{\"version\":2}\n[0.1,\"o\",\"x\"]\n");
+
+        // After a restart heaven picks up where the last recording left off.
+        let store = RecordingStore::new(Some(dir.clone()));
+        store.append("S1", b"[0.2,\"o\",\"y\"]\n").await.unwrap();
+        assert!(tokio::fs::read_to_string(&second).await.unwrap().ends_with("[0.2,\"o\",\"y\"]\n"));
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -2,6 +2,13 @@ async function abortJob(job) {
     await fetch("/port/" + job + "/abort");
 }
 
+async function switchActiveStates() {
+    const ports = document.getElementById("bulk-ports").value;
+    const states = document.getElementById("bulk-states").value;
+    const response = await fetch("/profile?ports=" + encodeURIComponent(ports) + "&states=" + encodeURIComponent(states));
+    document.getElementById("bulk-result").textContent = await response.text();
+}
+
 var reloaders = [];
 function createReloader(divId, page) {
     async function reloadHeader() {
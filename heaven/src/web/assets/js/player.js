@@ -0,0 +1,116 @@
+// Replays the recording of the current job, an asciinema v2 cast made by the angel and kept by heaven.
+class Player {
+    constructor() {
+        this.term = null;
+        this.output = [];
+        this.markers = [];
+        this.duration = 0;
+        this.position = 0;
+        this.next = 0;
+        this.speed = 1;
+        this.timer = null;
+    }
+
+    async load() {
+        this.pause();
+        const response = await fetch("recording.cast");
+        if (!response.ok) {
+            return;
+        }
+        const lines = (await response.text()).split("\n").filter(l => l.length > 0);
+        const header = JSON.parse(lines[0]);
+        const events = lines.slice(1).map(l => JSON.parse(l));
+        this.output = events.filter(e => e[1] === "o");
+        this.markers = events.filter(e => e[1] === "m");
+        this.duration = events.length > 0 ? events[events.length - 1][0] : 0;
+
+        if (this.term === null) {
+            this.term = new Terminal({cols: header.width, rows: header.height});
+            this.term.open(document.getElementById("player-terminal"));
+        }
+        document.getElementById("player-position").max = this.duration;
+        const markers = document.getElementById("player-markers");
+        markers.innerHTML = "";
+        for (const [time, , label] of this.markers) {
+            const tick = document.createElement("div");
+            tick.className = "player-marker";
+            tick.title = label;
+            tick.style.left = (this.duration > 0 ? 100 * time / this.duration : 0) + "%";
+            tick.onclick = () => this.seek(time);
+            markers.appendChild(tick);
+        }
+        this.seek(0);
+    }
+
+    // Jumping back means starting over, the terminal only goes forward.
+    seek(time) {
+        this.term.reset();
+        this.next = 0;
+        this.position = Math.min(time, this.duration);
+        this.render();
+    }
+
+    render() {
+        while (this.next < this.output.length && this.output[this.next][0] <= this.position) {
+            this.term.write(this.output[this.next][2]);
+            this.next++;
+        }
+        document.getElementById("player-position").value = this.position;
+        document.getElementById("player-time").textContent =
+            this.position.toFixed(1) + "s / " + this.duration.toFixed(1) + "s";
+        let stage = "";
+        for (const [time, , label] of this.markers) {
+            if (time <= this.position) {
+                stage = label;
+            }
+        }
+        document.getElementById("player-stage").textContent = stage;
+    }
+
+    play() {
+        if (this.position >= this.duration) {
+            this.seek(0);
+        }
+        let last = performance.now();
+        this.timer = setInterval(() => {
+            const now = performance.now();
+            this.position = Math.min(this.position + (now - last) / 1000 * this.speed, this.duration);
+            last = now;
+            this.render();
+            if (this.position >= this.duration) {
+                this.pause();
+            }
+        }, 50);
+        document.getElementById("player-play").textContent = "Pause";
+    }
+
+    pause() {
+        if (this.timer !== null) {
+            clearInterval(this.timer);
+            this.timer = null;
+        }
+        document.getElementById("player-play").textContent = "Play";
+    }
+
+    toggle() {
+        if (this.timer === null) {
+            this.play();
+        } else {
+            this.pause();
+        }
+    }
+
+    setSpeed(speed) {
+        this.speed = Number(speed);
+    }
+}
+
+var player = new Player();
+
+async function replayToggled(details) {
+    if (details.open) {
+        await player.load();
+    } else {
+        player.pause();
+    }
+}
@@ -1,9 +1,11 @@
 var term = new Terminal();
+// Identifies this page to heaven, only the page that took over the port may type into it.
+const session = Math.random().toString(36).slice(2) + Date.now().toString(36);
 term.open(document.getElementById('terminal'));
 
 function connect() {
     console.log("Connecting to socket...");
-    const webSocket = new WebSocket("serial");
+    const webSocket = new WebSocket("serial?session=" + session);
     webSocket.onclose = function (e) {
         console.log('Socket is closed. Reconnect will be attempted in 1 second.', e.reason);
         setTimeout(function () {
@@ -30,12 +32,71 @@ async function abortJob() {
     }
 }
 
+async function takeoverPort() {
+    resumeState = null;
+    const response = await fetch("takeover?session=" + session);
+    if (!response.ok) {
+        alert(await response.text());
+        return;
+    }
+    term.focus();
+}
+
+async function portCommand(command) {
+    await fetch(command);
+}
+
+// The header reloads every second, so remember what the operator picked.
+var resumeState = null;
+var gotoState = null;
+var activeStates = null;
+
+async function gotoPortState() {
+    if (gotoState) {
+        await fetch("goto?state=" + encodeURIComponent(gotoState));
+        gotoState = null;
+    }
+}
+async function setActiveStates() {
+    if (activeStates) {
+        await fetch("profile?states=" + encodeURIComponent(activeStates));
+        activeStates = null;
+    }
+}
+async function releasePort() {
+    const select = document.getElementById("resume-state");
+    const state = select ? select.value : "";
+    await fetch("release?state=" + encodeURIComponent(state));
+    resumeState = null;
+}
+
+function restoreResumeState() {
+    const select = document.getElementById("resume-state");
+    if (select && resumeState !== null) {
+        select.value = resumeState;
+    }
+    const input = document.getElementById("goto-state");
+    if (input && gotoState !== null) {
+        input.value = gotoState;
+    }
+    const states = document.getElementById("active-states");
+    if (states && activeStates !== null) {
+        states.value = activeStates;
+    }
+}
+
 var reloaders = [];
 function createReloader(divId, page) {
     async function reloadHeader() {
+        // Do not pull the rug from under someone typing or picking a state.
+        const active = document.activeElement;
+        if (active && ["INPUT", "SELECT"].includes(active.tagName) && document.getElementById(divId).contains(active)) {
+            return;
+        }
         const response = await fetch(page);
         const data = await response.text();
         document.getElementById(divId).innerHTML = data;
+        restoreResumeState();
     }
 
     reloaders.push(setInterval(reloadHeader, 1000));
@@ -1,14 +1,14 @@
 use chrono::{DateTime, Utc};
 use chrono_humanize::HumanTime;
-use cthulhu_common::devinfo::DeviceInformation;
+use cthulhu_common::devinfo::{DeviceInformation, SeverityPolicy};
 use cthulhu_common::job::{JobData, JobStatus};
 
 pub trait PortStatusExt {
-    fn get_css_backgroundcolor(&self) -> String;
+    fn get_css_backgroundcolor(&self, policy: &SeverityPolicy) -> String;
 }
 impl PortStatusExt for JobData {
-    fn get_css_backgroundcolor(&self) -> String {
-        match self.get_status() {
+    fn get_css_backgroundcolor(&self, policy: &SeverityPolicy) -> String {
+        match self.get_status(policy) {
             JobStatus::Idle => "var(--primary-background)".to_string(),
             JobStatus::FinishSuccess => "#00ff00".to_string(),
             JobStatus::FinishWarning => "#ff9933".to_string(),
@@ -28,6 +28,11 @@ impl PortStatusExt for JobData {
                 }
             },
             JobStatus::Fatal => "#ff33dd".to_string(),
+            JobStatus::PortDisconnected => "#999999".to_string(),
+            JobStatus::TakenOver => "#ffff00".to_string(),
+            JobStatus::Paused => "#ffcc00".to_string(),
+            JobStatus::Drained => "#cccc66".to_string(),
+            JobStatus::Offline => "#666666".to_string(),
         }
     }
 }
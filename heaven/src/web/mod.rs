@@ -1,6 +1,9 @@
 use crate::manager::JobManager;
 use crate::mqtt::{BroadcastSender, MQTTSender};
-use crate::web::pages::{abort, restart_all};
+use crate::web::pages::{
+    abort, drain, drain_all, goto_state, pause, release, restart_all, restart_all_now, resume,
+    set_active_states, set_active_states_all, step, takeover,
+};
 use crate::web::serial::serial_handler;
 use axum::body::Body;
 use axum::extract::{Path, Request};
@@ -9,6 +12,8 @@ use axum::response::{IntoResponse, Response};
 use axum::routing::get;
 use axum::{Router, middleware};
 use axum::middleware::Next;
+use cthulhu_common::devinfo::SeverityPolicy;
+use cthulhu_common::metrics::Metrics;
 use cthulhu_config::heaven::HeavenConfig;
 use include_dir::{Dir, include_dir};
 use tower::ServiceBuilder;
@@ -25,6 +30,8 @@ struct WebState {
     manager: JobManager,
     mqtt: MQTTSender,
     broadcast: BroadcastSender,
+    metrics: Metrics,
+    severity_policy: SeverityPolicy,
 }
 
 pub async fn web_main(
@@ -32,21 +39,42 @@ pub async fn web_main(
     manager: JobManager,
     mqtt: MQTTSender,
     broadcast: BroadcastSender,
+    metrics: Metrics,
 ) -> color_eyre::Result<()> {
     let state = WebState {
         manager,
         mqtt,
         broadcast,
+        metrics,
+        severity_policy: config.severity_policy.clone(),
     };
     let app = Router::new()
         .route("/", get(pages::index::index))
         .route("/portstatus.html", get(pages::index::port_status))
         .route("/restart", get(restart_all))
+        .route("/restart-now", get(restart_all_now))
+        .route("/drain", get(drain_all))
+        .route("/metrics", get(pages::metrics))
+        .route("/profile", get(set_active_states_all))
         .route("/port/{port_label}/", get(pages::port::port))
         .route("/port/{port_label}/header.html", get(pages::port::header))
         .route("/port/{port_label}/devinfo.html", get(pages::port::footer))
         .route("/port/{port_label}/abort", get(abort))
+        .route("/port/{port_label}/takeover", get(takeover))
+        .route("/port/{port_label}/release", get(release))
+        .route("/port/{port_label}/pause", get(pause))
+        .route("/port/{port_label}/drain", get(drain))
+        .route("/port/{port_label}/resume", get(resume))
+        .route("/port/{port_label}/step", get(step))
+        .route("/port/{port_label}/goto", get(goto_state))
+        .route("/port/{port_label}/profile", get(set_active_states))
         .route("/port/{port_label}/serial", get(serial_handler))
+        .route("/port/{port_label}/recording.cast", get(pages::port::recording))
+        .route("/certificates/", get(pages::certificate::index))
+        .route("/certificates/batch.html", get(pages::certificate::batch_html))
+        .route("/certificates/batch.json", get(pages::certificate::batch_json))
+        .route("/certificates/{serial}/certificate.html", get(pages::certificate::serial_html))
+        .route("/certificates/{serial}/certificate.json", get(pages::certificate::serial_json))
         .route("/assets/{*path}", get(static_path))
         .layer(
             ServiceBuilder::new()
@@ -0,0 +1,154 @@
+use crate::web::WebState;
+use axum::Json;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use cthulhu_common::certificate::{SignedErasureCertificate, verify_certificate};
+use maud::{DOCTYPE, Markup, html};
+use serde::Deserialize;
+use tracing::warn;
+
+#[derive(Deserialize)]
+pub struct BatchQuery {
+    /// Comma separated list of serial numbers.
+    serials: String,
+}
+
+async fn load(state: &WebState, serials: &[&str]) -> Result<Vec<SignedErasureCertificate>, Response> {
+    let certificates = state
+        .manager
+        .certificates()
+        .for_serials(serials)
+        .await
+        .map_err(|e| {
+            warn!("Failed to load certificates: {e:?}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Error").into_response()
+        })?;
+    if certificates.is_empty() {
+        return Err((StatusCode::NOT_FOUND, "No certificates found").into_response());
+    }
+    Ok(certificates)
+}
+
+fn render(certificates: &[SignedErasureCertificate]) -> Markup {
+    html! {
+        (DOCTYPE)
+        html {
+            head {
+                meta charset="utf-8";
+                link rel="stylesheet" href="/assets/css/certificate.css";
+            }
+            body {
+                @for signed in certificates {
+                    @let c = &signed.certificate;
+                    div class="certificate" {
+                        h1 { "Certificate of erasure" }
+                        table {
+                            tr { td { "Serial number:" } td { (c.serial_number.as_deref().unwrap_or("Unknown")) } }
+                            tr { td { "Vendor:" } td { (c.vendor.as_deref().unwrap_or("Unknown")) } }
+                            tr { td { "Model:" } td { (c.model.as_deref().unwrap_or("Unknown")) } }
+                            tr { td { "Software version:" } td { (c.software_version.as_deref().unwrap_or("Unknown")) } }
+                            tr { td { "Verification:" } td { (format!("{:?}", c.verification)) } }
+                            tr { td { "Started:" } td { (c.job_started.map(|t| t.to_rfc3339()).unwrap_or_default()) } }
+                            tr { td { "Ended:" } td { (c.job_ended.map(|t| t.to_rfc3339()).unwrap_or_default()) } }
+                            tr { td { "Port:" } td { (c.port_id) } }
+                            tr { td { "Transcript SHA-256:" } td class="hash" { (c.transcript_sha256.as_deref().unwrap_or("Unavailable")) } }
+                            tr { td { "Public key:" } td class="hash" { (signed.public_key.as_deref().unwrap_or("Unsigned")) } }
+                            tr { td { "Signature:" } td class="hash" { (signed.signature.as_deref().unwrap_or("Unsigned")) } }
+                            tr { td { "Signature check:" } td {
+                                @match verify_certificate(signed) {
+                                    Ok(()) => { "Valid" }
+                                    Err(e) => { "Failed: " (e) }
+                                }
+                            } }
+                        }
+                        h3 { "Wipe method:" }
+                        ol {
+                            @for (t, stage) in c.wipe_method.iter() {
+                                li { (stage) " (" (t.to_rfc3339()) ")" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub async fn index(State(state): State<WebState>) -> Result<Markup, Response> {
+    let certificates = state.manager.certificates().list().await.map_err(|e| {
+        warn!("Failed to load certificates: {e:?}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Error").into_response()
+    })?;
+
+    Ok(html! {
+        (DOCTYPE)
+        html {
+            head {
+                meta charset="utf-8";
+                link rel="stylesheet" href="/assets/css/certificate.css";
+            }
+            body {
+                h1 { "Erasure certificates" }
+                table {
+                    tr {
+                        th { "Serial number" }
+                        th { "Model" }
+                        th { "Ended" }
+                        th { "Verification" }
+                        th { "Port" }
+                        th { "Download" }
+                    }
+                    @for signed in certificates.iter().rev() {
+                        @let c = &signed.certificate;
+                        @let serial = c.serial_number.as_deref().unwrap_or("");
+                        tr {
+                            td { (serial) }
+                            td { (c.model.as_deref().unwrap_or("")) }
+                            td { (c.job_ended.map(|t| t.to_rfc3339()).unwrap_or_default()) }
+                            td { (format!("{:?}", c.verification)) }
+                            td { (c.port_id) }
+                            td {
+                                @if !serial.is_empty() {
+                                    a href={ "/certificates/" (serial) "/certificate.html" } { "HTML" }
+                                    " "
+                                    a href={ "/certificates/" (serial) "/certificate.json" } { "JSON" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+pub async fn serial_html(
+    State(state): State<WebState>,
+    Path(serial): Path<String>,
+) -> Result<Markup, Response> {
+    Ok(render(&load(&state, &[&serial]).await?))
+}
+
+pub async fn serial_json(
+    State(state): State<WebState>,
+    Path(serial): Path<String>,
+) -> Result<Json<Vec<SignedErasureCertificate>>, Response> {
+    Ok(Json(load(&state, &[&serial]).await?))
+}
+
+pub async fn batch_html(
+    State(state): State<WebState>,
+    Query(query): Query<BatchQuery>,
+) -> Result<Markup, Response> {
+    let serials: Vec<&str> = query.serials.split(',').map(|s| s.trim()).collect();
+    Ok(render(&load(&state, &serials).await?))
+}
+
+pub async fn batch_json(
+    State(state): State<WebState>,
+    Query(query): Query<BatchQuery>,
+) -> Result<Json<Vec<SignedErasureCertificate>>, Response> {
+    let serials: Vec<&str> = query.serials.split(',').map(|s| s.trim()).collect();
+    Ok(Json(load(&state, &serials).await?))
+}
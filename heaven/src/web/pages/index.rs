@@ -1,9 +1,12 @@
 use axum::extract::State;
+use std::collections::BTreeSet;
 use maud::{html, Markup, DOCTYPE};
 use crate::web::helpers::*;
 use crate::web::WebState;
 
 pub async fn index(s: State<WebState>) -> Markup {
+    let ports = s.manager.get_ports().await;
+    let profiles: BTreeSet<String> = ports.iter().map(|p| p.data.active_states.join(", ")).collect();
     let ps = port_status(s).await;
     html! {
         (DOCTYPE)
@@ -13,8 +16,23 @@ pub async fn index(s: State<WebState>) -> Markup {
                 link rel="stylesheet" href="/assets/css/index.css";
                 script src="/assets/js/index.js" {}
             }
-            body id="portstatus" {
-                (ps)
+            body {
+                div id="portstatus" {
+                    (ps)
+                }
+                div id="bulk-switch" {
+                    "Switch state files of ports matching "
+                    input id="bulk-ports" placeholder="^S1";
+                    " to "
+                    input id="bulk-states" list="known-profiles" placeholder="wipe, provision";
+                    datalist id="known-profiles" {
+                        @for profile in profiles.iter() {
+                            option value=(profile) {}
+                        }
+                    }
+                    button onclick="switchActiveStates()" { "Switch" }
+                    span id="bulk-result" {}
+                }
             }
         }
     }
@@ -35,7 +53,7 @@ pub async fn port_status(State(state): State<WebState>) -> Markup {
                             @let port_n = (j * column_height) + i;
                             @if port_n < ports.len() {
                                 @let port = &ports[port_n];
-                                table class="inner" style={"background-color: " (port.data.get_css_backgroundcolor())} {
+                                table class="inner" style={"background-color: " (port.data.get_css_backgroundcolor(&state.severity_policy))} {
                                     tr {
                                         td {
                                             (port.data.get_last_updated().map(|v| v.timeago()).unwrap_or("UNKN".to_string()))
@@ -48,7 +66,7 @@ pub async fn port_status(State(state): State<WebState>) -> Markup {
                                             }
                                         }
                                         td {
-                                            (port.data.get_status())
+                                            (port.data.get_status(&state.severity_policy))
                                         }
                                     }
                                     tr {
@@ -67,10 +85,21 @@ pub async fn port_status(State(state): State<WebState>) -> Markup {
                                             (port.data.get_current_stage().unwrap_or("UNKN"))
                                         }
                                     }
+                                    tr {
+                                        td colspan="3" {
+                                            (port.data.active_states.join(", "))
+                                            @if let Some(next) = port.data.next_active_states.as_ref() {
+                                                " → " (next.join(", "))
+                                            }
+                                            @if let Some(rejected) = port.data.rejected_active_states.as_ref() {
+                                                " (refused " (rejected.states.join(", ")) ")"
+                                            }
+                                        }
+                                    }
                                     tr {
                                         td {
                                             button onclick={ "abortJob('" (port.data.label) "')" } {
-                                                @if port.data.get_status().is_finished() {
+                                                @if port.data.get_status(&state.severity_policy).is_finished() {
                                                     "New Job"
                                                 } @else {
                                                     "Abort Job"
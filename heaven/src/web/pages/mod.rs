@@ -1,25 +1,180 @@
 use crate::web::WebState;
-use axum::extract::{Path, State};
-use axum::http::StatusCode;
+use axum::extract::{Path, Query, State};
+use axum::http::{StatusCode, header};
 use axum::response::{Html, IntoResponse, Response};
+use cthulhu_common::metrics::CONTENT_TYPE;
 use cthulhu_common::status::JobCommand;
+use regex::Regex;
+use serde::Deserialize;
 use tracing::warn;
 
+pub mod certificate;
 pub mod index;
 pub mod port;
 
-pub async fn restart_all(State(state): State<WebState>) -> Response {
-    match state.mqtt.broadcast_command(JobCommand::RestartAngel).await {
+async fn broadcast(state: &WebState, command: JobCommand) -> Response {
+    match state.mqtt.broadcast_command(command.clone()).await {
         Ok(_) => {
             Html("OK").into_response()
         }
         Err(e) => {
-            warn!("Failed to send restart: {e:?}");
+            warn!("Failed to send {command:?}: {e:?}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Error").into_response()
+        }
+    }
+}
+
+pub async fn restart_all(State(state): State<WebState>) -> Response {
+    broadcast(&state, JobCommand::RestartWhenIdle).await
+}
+
+pub async fn restart_all_now(State(state): State<WebState>) -> Response {
+    broadcast(&state, JobCommand::RestartNow).await
+}
+
+pub async fn drain_all(State(state): State<WebState>) -> Response {
+    broadcast(&state, JobCommand::Drain).await
+}
+
+pub async fn metrics(State(state): State<WebState>) -> Response {
+    (
+        [(header::CONTENT_TYPE, CONTENT_TYPE)],
+        state.metrics.render(),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct TakeoverQuery {
+    /// Session of the page taking over, generated by port.js.
+    session: String,
+}
+
+pub async fn takeover(
+    State(state): State<WebState>,
+    Path(port_label): Path<String>,
+    Query(query): Query<TakeoverQuery>,
+) -> Response {
+    if let Some(session) = state.manager.takeover_session(&port_label).await
+        && session != query.session
+    {
+        return (StatusCode::CONFLICT, "Port is taken over by another session").into_response();
+    }
+    port_command(&state, &port_label, JobCommand::Takeover { session: query.session }).await
+}
+
+#[derive(Deserialize)]
+pub struct ReleaseQuery {
+    /// State to resume the job in, the current one if unset.
+    state: Option<String>,
+}
+
+pub async fn release(
+    State(state): State<WebState>,
+    Path(port_label): Path<String>,
+    Query(query): Query<ReleaseQuery>,
+) -> Response {
+    let command = JobCommand::Release { state: query.state.filter(|s| !s.is_empty()) };
+    port_command(&state, &port_label, command).await
+}
+
+async fn port_command(state: &WebState, port_label: &str, command: JobCommand) -> Response {
+    match state.mqtt.send_command(port_label, command.clone()).await {
+        Ok(_) => Html("DONE").into_response(),
+        Err(e) => {
+            warn!("Failed to send {command:?} to {port_label}: {e:?}");
             (StatusCode::INTERNAL_SERVER_ERROR, "Error").into_response()
         }
     }
 }
 
+pub async fn drain(State(state): State<WebState>, Path(port_label): Path<String>) -> Response {
+    port_command(&state, &port_label, JobCommand::Drain).await
+}
+
+pub async fn pause(State(state): State<WebState>, Path(port_label): Path<String>) -> Response {
+    port_command(&state, &port_label, JobCommand::Pause).await
+}
+
+pub async fn resume(State(state): State<WebState>, Path(port_label): Path<String>) -> Response {
+    port_command(&state, &port_label, JobCommand::Resume).await
+}
+
+pub async fn step(State(state): State<WebState>, Path(port_label): Path<String>) -> Response {
+    port_command(&state, &port_label, JobCommand::Step).await
+}
+
+#[derive(Deserialize)]
+pub struct GotoQuery {
+    state: String,
+}
+
+pub async fn goto_state(
+    State(state): State<WebState>,
+    Path(port_label): Path<String>,
+    Query(query): Query<GotoQuery>,
+) -> Response {
+    port_command(&state, &port_label, JobCommand::GotoState(query.state)).await
+}
+
+#[derive(Deserialize)]
+pub struct ActiveStatesQuery {
+    /// Comma separated state file ids.
+    states: String,
+    /// Regex picking the ports to switch, all ports if unset.
+    ports: Option<String>,
+}
+
+fn parse_states(states: &str) -> Vec<String> {
+    states
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+pub async fn set_active_states(
+    State(state): State<WebState>,
+    Path(port_label): Path<String>,
+    Query(query): Query<ActiveStatesQuery>,
+) -> Response {
+    let states = parse_states(&query.states);
+    if states.is_empty() {
+        return (StatusCode::BAD_REQUEST, "No state files given").into_response();
+    }
+    port_command(&state, &port_label, JobCommand::SetActiveStates(states)).await
+}
+
+/// Switch the state files of every port whose label matches `ports`.
+pub async fn set_active_states_all(
+    State(state): State<WebState>,
+    Query(query): Query<ActiveStatesQuery>,
+) -> Response {
+    let ports = match Regex::new(query.ports.as_deref().unwrap_or_default()) {
+        Ok(ports) => ports,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("Invalid port pattern: {e}")).into_response(),
+    };
+    let states = parse_states(&query.states);
+    if states.is_empty() {
+        return (StatusCode::BAD_REQUEST, "No state files given").into_response();
+    }
+    let mut switched = Vec::new();
+    for port in state.manager.get_ports().await {
+        if !ports.is_match(&port.data.label) {
+            continue;
+        }
+        let command = JobCommand::SetActiveStates(states.clone());
+        if let Err(e) = state.mqtt.send_command(&port.data.label, command).await {
+            warn!("Failed to switch the state files of {}: {e:?}", port.data.label);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Error").into_response();
+        }
+        switched.push(port.data.label);
+    }
+    switched.sort();
+    // The angels check the state files, a refusal shows up on the port.
+    Html(format!("Asked {} to switch", switched.join(", "))).into_response()
+}
+
 pub async fn abort(
     State(state): State<WebState>,
     Path(port_label): Path<String>,
@@ -1,10 +1,14 @@
 use crate::web::WebState;
 use crate::web::helpers::{DateTimeAgo, PortStatusExt};
 use axum::extract::{Path, State};
-use axum::http::StatusCode;
+use axum::http::{StatusCode, header};
 use axum::response::{IntoResponse, Response};
 use chrono::Utc;
+use cthulhu_common::devinfo::DeviceInformation;
+use cthulhu_common::job::OPERATOR_ACTION_PREFIX;
+use std::collections::BTreeSet;
 use maud::{DOCTYPE, Markup, html};
+use tracing::warn;
 
 pub async fn header(
     State(state): State<WebState>,
@@ -16,7 +20,7 @@ pub async fn header(
         return Err((StatusCode::NOT_FOUND, "Port not found").into_response());
     };
 
-    let ps = format!("{:?}", port.data.get_status());
+    let ps = format!("{:?}", port.data.get_status(&state.severity_policy));
 
     Ok(html! {
         table {
@@ -37,7 +41,7 @@ pub async fn header(
                 td {
                     "Current status:"
                 }
-                td style={"background-color: " (port.data.get_css_backgroundcolor())} {
+                td style={"background-color: " (port.data.get_css_backgroundcolor(&state.severity_policy))} {
                     (ps)
                 }
                 td {
@@ -59,13 +63,70 @@ pub async fn header(
                 }
                 td {
                     button onclick={ "abortJob('" (port.data.label) "')" } {
-                        @if port.data.get_status().is_finished() {
+                        @if port.data.get_status(&state.severity_policy).is_finished() {
                             "New Job"
                         } @else {
                             "Abort Job"
                         }
                     }
                 }
+                td {
+                    @if port.data.paused {
+                        button onclick="portCommand('resume')" { "Resume" }
+                        button onclick="portCommand('step')" { "Step" }
+                    } @else {
+                        button onclick="portCommand('pause')" { "Pause" }
+                    }
+                    button onclick="portCommand('drain')" { "Drain" }
+                }
+                td {
+                    input id="goto-state" list="known-states" placeholder="State" oninput="gotoState = this.value";
+                    datalist id="known-states" {
+                        @for s in port.data.state_history.iter().map(|(_, s)| s).filter(|s| !s.starts_with(OPERATOR_ACTION_PREFIX)).collect::<BTreeSet<_>>() {
+                            option value=(s) {}
+                        }
+                    }
+                    button onclick="gotoPortState()" { "Go to" }
+                }
+                @if let Some(takeover) = port.data.takeover.as_ref() {
+                    td {
+                        "Taken over " (takeover.since.timeago()) ", resume in:"
+                    }
+                    td {
+                        @let current = port.data.get_current_stage().unwrap_or_default();
+                        select id="resume-state" onchange="resumeState = this.value" {
+                            @for s in takeover.states.iter() {
+                                option value=(s) selected[s == current] { (s) }
+                            }
+                        }
+                        button onclick="releasePort()" { "Release" }
+                    }
+                } @else {
+                    td {
+                        button onclick="takeoverPort()" { "Take over" }
+                    }
+                }
+            }
+            tr {
+                td {
+                    "State files:"
+                }
+                td colspan="3" {
+                    (port.data.active_states.join(", "))
+                    @if let Some(next) = port.data.next_active_states.as_ref() {
+                        " (" (next.join(", ")) " after this job)"
+                    }
+                    @if let Some(rejected) = port.data.rejected_active_states.as_ref() {
+                        br;
+                        span class="rejected" {
+                            "Refused " (rejected.states.join(", ")) ": " (rejected.reason)
+                        }
+                    }
+                }
+                td colspan="2" {
+                    input id="active-states" placeholder="wipe, provision" oninput="activeStates = this.value";
+                    button onclick="setActiveStates()" { "Switch" }
+                }
             }
         }
     })
@@ -86,13 +147,56 @@ pub async fn footer(
                 td {
                     h3 { "Device Information:"}
                     ul {
-                        @for info in port.data.info_items.iter() {
+                        @for info in port.data.info_items.iter().filter(|i| !i.is_inventory()) {
                             li {
                                 (info)
                             }
                         }
                     }
                 }
+                td {
+                    h3 { "Hardware inventory:" }
+                    ul {
+                        @for info in port.data.info_items.iter() {
+                            @match info {
+                                DeviceInformation::PortCount(n) => li { "Ports: " (n) },
+                                DeviceInformation::PowerSupplies(psus) => li {
+                                    "Power supplies:"
+                                    ul {
+                                        @for psu in psus {
+                                            li { (psu.name) ": " (psu.status) }
+                                        }
+                                    }
+                                },
+                                DeviceInformation::Fans(fans) => li {
+                                    "Fans:"
+                                    ul {
+                                        @for fan in fans {
+                                            li { (fan.name) ": " (fan.status) }
+                                        }
+                                    }
+                                },
+                                DeviceInformation::Transceivers(xcvrs) => li {
+                                    "Transceivers:"
+                                    ul {
+                                        @for xcvr in xcvrs {
+                                            li { (xcvr.port) ": " (xcvr.model) " (" (xcvr.serial) ")" }
+                                        }
+                                    }
+                                },
+                                DeviceInformation::Licenses(licenses) => li {
+                                    "Licenses:"
+                                    ul {
+                                        @for license in licenses {
+                                            li { (license) }
+                                        }
+                                    }
+                                },
+                                _ => {},
+                            }
+                        }
+                    }
+                }
                 td {
                     h3 { "Stage history:" }
                     ul {
@@ -108,6 +212,25 @@ pub async fn footer(
     })
 }
 
+/// The recording of the current job, as the angel made it, with the stage transitions as markers.
+pub async fn recording(State(state): State<WebState>, Path(port_label): Path<String>) -> Response {
+    let path = match state.manager.recordings().latest(&port_label).await {
+        Ok(Some(path)) => path,
+        Ok(None) => return (StatusCode::NOT_FOUND, "No recording").into_response(),
+        Err(e) => {
+            warn!("Failed to find the recording of port {port_label}: {e:?}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Error").into_response();
+        }
+    };
+    match tokio::fs::read(&path).await {
+        Ok(cast) => ([(header::CONTENT_TYPE, "application/x-asciicast")], cast).into_response(),
+        Err(e) => {
+            warn!("Failed to read recording {path:?}: {e:?}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Error").into_response()
+        }
+    }
+}
+
 pub async fn port(
     State(state): State<WebState>,
     Path(port_label): Path<String>,
@@ -129,10 +252,29 @@ pub async fn port(
                     (h)
                 }
                 div id="terminal" {}
+                details id="replay" ontoggle="replayToggled(this)" {
+                    summary { "Replay" }
+                    div id="player-controls" {
+                        button id="player-play" onclick="player.toggle()" { "Play" }
+                        select onchange="player.setSpeed(this.value)" {
+                            @for speed in ["0.5", "1", "2", "4", "16", "64"] {
+                                option value=(speed) selected[speed == "1"] { (speed) "x" }
+                            }
+                        }
+                        span id="player-time" {}
+                        span id="player-stage" {}
+                    }
+                    div id="player-timeline" {
+                        input id="player-position" type="range" min="0" step="0.1" value="0" oninput="player.seek(Number(this.value))";
+                        div id="player-markers" {}
+                    }
+                    div id="player-terminal" {}
+                }
                 div id="devinfo" {
                     (f)
                 }
                 script src="/assets/js/port.js" {}
+                script src="/assets/js/player.js" {}
             }
         }
     })
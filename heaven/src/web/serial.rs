@@ -3,13 +3,24 @@ use crate::mqtt::MQTTBroadcast;
 use crate::web::WebState;
 use axum::body::Bytes;
 use axum::extract::ws::{Message, WebSocket};
-use axum::extract::{Path, State, WebSocketUpgrade};
+use axum::extract::{Path, Query, State, WebSocketUpgrade};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+#[derive(Deserialize)]
+pub struct SerialQuery {
+    /// Session of the page, compared against the one that took over the port.
+    #[serde(default)]
+    session: String,
+}
 
 pub async fn serial_handler(
     State(state): State<WebState>,
     Path(port_label): Path<String>,
+    Query(query): Query<SerialQuery>,
     ws: WebSocketUpgrade,
 ) -> Response {
     let port = if let Some(v) = state.manager.get_port(&port_label).await {
@@ -17,28 +28,75 @@ pub async fn serial_handler(
     } else {
         return (StatusCode::NOT_FOUND, "Port not found").into_response();
     };
-    ws.on_upgrade(|ws| serial_handle_socket(ws, state, port))
+    ws.on_upgrade(|ws| serial_handle_socket(ws, state, port, query.session))
 }
 
-async fn serial_handle_socket(mut socket: WebSocket, state: WebState, port: PortManagerEntry) {
+async fn serial_handle_socket(mut socket: WebSocket, state: WebState, port: PortManagerEntry, session: String) {
     let mut receiver = state.broadcast.subscribe();
+    // Only tell a watching session once per takeover that its keystrokes go nowhere.
+    let mut rejected = false;
 
     socket
         .send(Message::Binary(Bytes::from(port.log_buffer)))
         .await
         .unwrap();
 
-    while let Ok(message) = receiver.recv().await {
-        match message {
-            MQTTBroadcast::SerialData { label, data } => {
-                if label == port.data.label {
-                    socket
-                        .send(Message::Binary(Bytes::from(data)))
-                        .await
-                        .unwrap();
+    loop {
+        tokio::select! {
+            message = receiver.recv() => match message {
+                Ok(MQTTBroadcast::SerialData { label, data }) => {
+                    if label == port.data.label {
+                        socket
+                            .send(Message::Binary(Bytes::from(data)))
+                            .await
+                            .unwrap();
+                    }
+                }
+                Ok(_) => {}
+                // Start over from what the manager has, it only skips what it missed itself.
+                Err(RecvError::Lagged(n)) => {
+                    warn!("Serial websocket for {} skipped {n} messages, resyncing.", port.data.label);
+                    state.metrics.lagged("web", n);
+                    receiver = receiver.resubscribe();
+                    let log_buffer = match state.manager.get_port(&port.data.label).await {
+                        Some(p) => p.log_buffer,
+                        None => return,
+                    };
+                    // ESC c resets the terminal before the log is replayed into it.
+                    let mut data = b"\x1bc".to_vec();
+                    data.extend(log_buffer);
+                    if socket.send(Message::Binary(Bytes::from(data))).await.is_err() {
+                        return;
+                    }
+                }
+                Err(RecvError::Closed) => return,
+            },
+            // Keystrokes only reach the device from the session that has taken over the port.
+            input = socket.recv() => {
+                let data = match input {
+                    Some(Ok(Message::Text(t))) => t.as_bytes().to_vec(),
+                    Some(Ok(Message::Binary(b))) => b.to_vec(),
+                    Some(Ok(_)) => continue,
+                    _ => return,
+                };
+                match state.manager.takeover_session(&port.data.label).await {
+                    Some(owner) if owner == session => {
+                        rejected = false;
+                        if let Err(e) = state.mqtt.send_input(&port.data.label, data).await {
+                            warn!("Failed to send input: {e:?}");
+                        }
+                    }
+                    Some(_) if !rejected => {
+                        rejected = true;
+                        let notice = "\r\n[Port is taken over by another session, input ignored]\r\n";
+                        if socket.send(Message::Text(notice.into())).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(_) => {}
+                    None => rejected = false,
                 }
-            }
-            _ => {}
+            },
         }
     }
 }
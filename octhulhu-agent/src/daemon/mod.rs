@@ -2,11 +2,11 @@ use crate::daemon::tracker::PortTracker;
 use crate::serial;
 use crate::serial::discovery::DiscoveredDevice;
 use crate::serial::{SerialPortManager, SerialPortMessage};
+use cthulhu_common::mqtt::mqtt_options;
 use cthulhu_common::status::{JobCommand, JobUpdate};
 use cthulhu_config::octhulhu::{OcthulhuConfig, OcthulhuHeavenConfig};
 use regex::Regex;
 use rumqttc::{AsyncClient, Event, EventLoop, Incoming, MqttOptions, QoS};
-use std::time::Duration;
 use tracing::{info, warn};
 
 mod tracker;
@@ -14,9 +14,7 @@ mod tracker;
 async fn mqtt_options_from_config(
     config: &OcthulhuHeavenConfig,
 ) -> color_eyre::Result<MqttOptions> {
-    let mut mqttoptions = MqttOptions::new(&config.id, &config.host, config.port);
-    mqttoptions.set_keep_alive(Duration::from_secs(5));
-    Ok(mqttoptions)
+    mqtt_options(&config.id, &config.host, config.port, &config.security).await
 }
 
 pub async fn daemon(conf: OcthulhuConfig) -> color_eyre::Result<()> {
@@ -38,7 +36,10 @@ pub async fn daemon(conf: OcthulhuConfig) -> color_eyre::Result<()> {
 
     info!("Starting MQTT thread...");
     let mut handles = Vec::new();
-    let port_tracker = PortTracker::with_serial_port_manager(serial_port_manager.clone());
+    let port_tracker = PortTracker::with_serial_port_manager(
+        serial_port_manager.clone(),
+        conf.severity_policy.clone(),
+    );
 
     {
         let port_tracker = port_tracker.clone();
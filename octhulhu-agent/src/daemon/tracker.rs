@@ -5,22 +5,27 @@ use std::collections::BTreeMap;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tracing::{debug, info};
-use cthulhu_common::devinfo::DeviceInformation;
+use cthulhu_common::devinfo::{DeviceInformation, SeverityPolicy};
 use crate::serial::SerialPortManager;
 
 #[derive(Clone)]
 pub struct PortTracker {
     serial_port_manager: SerialPortManager,
+    severity_policy: SeverityPolicy,
     inner: Arc<Mutex<PortTrackerInner>>,
 }
 
 impl PortTracker {
-    pub fn with_serial_port_manager(serial_port_manager: SerialPortManager) -> Self {
+    pub fn with_serial_port_manager(
+        serial_port_manager: SerialPortManager,
+        severity_policy: SeverityPolicy,
+    ) -> Self {
         Self {
             inner: Arc::new(Mutex::new(PortTrackerInner {
                 entries: BTreeMap::new(),
             })),
             serial_port_manager,
+            severity_policy,
         }
     }
 
@@ -36,6 +41,7 @@ impl PortTracker {
             PortTrackerEntry {
                 data: JobData::with_label(label),
                 serial_port_manager: self.serial_port_manager.clone(),
+                severity_policy: self.severity_policy.clone(),
                 port_idx,
                 board_sn: board_sn.to_string(),
                 module_present: None,
@@ -94,6 +100,7 @@ struct PortTrackerInner {
 struct PortTrackerEntry {
     data: JobData,
     serial_port_manager: SerialPortManager,
+    severity_policy: SeverityPolicy,
     board_sn: String,
     port_idx: u8,
     module_present: Option<bool>,
@@ -109,7 +116,7 @@ fn gamma_correct(i: u8) -> u8 {
 
 impl PortTrackerEntry {
     async fn update_led_color(&mut self) -> color_eyre::Result<()> {
-        let (r, g, b) = match self.data.get_status() {
+        let (r, g, b) = match self.data.get_status(&self.severity_policy) {
             JobStatus::Idle => (127, 127, 127),
             JobStatus::FinishSuccess => (0, 255, 0),
             JobStatus::FinishWarning => (0xff, 0x99, 0x33),
@@ -129,6 +136,11 @@ impl PortTrackerEntry {
                 }
             },
             JobStatus::Fatal => (0xff, 0x33, 0xdd),
+            JobStatus::PortDisconnected => (0xff, 0xff, 0x00),
+            JobStatus::TakenOver => (0xff, 0xff, 0xff),
+            JobStatus::Paused => (0xff, 0xcc, 0x00),
+            JobStatus::Drained => (0x33, 0x33, 0x33),
+            JobStatus::Offline => (0, 0, 0),
         };
         debug!("Color: {} {} {}", r, g, b);
         if self.switch_present.unwrap_or(false) && self.module_present.unwrap_or(false) {